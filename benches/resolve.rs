@@ -0,0 +1,85 @@
+//! Compares finding moved directories by walking the tree with looking them
+//! up in the index.
+//!
+//! Run with `cargo bench --bench resolve`. `DIRMON_BENCH_DIRS` sets the
+//! approximate number of directories in the generated tree (default 20000).
+
+use dirmon::bench::{resolve_by_walk, resolve_move, DirIndex, DirSnapshot, MoveResolution};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+const MOVES: usize = 20;
+
+fn main() {
+    let target: usize = std::env::var("DIRMON_BENCH_DIRS")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(20_000);
+    let base = std::env::temp_dir().join(format!("dirmon-bench-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&base);
+
+    // Three levels: top folders of 20 subfolders of 10 leaves each
+    let tops = (target / 231).max(MOVES);
+    for top in 0..tops {
+        for sub in 0..20 {
+            for leaf in 0..10 {
+                let path = base.join(format!("top{top}/sub{sub}/leaf{leaf}"));
+                fs::create_dir_all(&path).expect("create bench tree");
+            }
+        }
+    }
+
+    let started = Instant::now();
+    let mut index = DirIndex::build(&base, None);
+    let build_time = started.elapsed();
+    println!(
+        "{} directories, index built in {:?}",
+        index.len(),
+        build_time
+    );
+
+    // Move one subfolder of each of the first tops into the last one, the
+    // way a removal followed by a search would see it
+    let archive = base.join(format!("top{}", tops - 1));
+    let mut moved: Vec<(PathBuf, PathBuf, DirSnapshot)> = Vec::new();
+    for top in 0..MOVES {
+        let from = base.join(format!("top{top}/sub5"));
+        let to = archive.join(format!("moved{top}"));
+        let snapshot = DirSnapshot::take(&from);
+        fs::rename(&from, &to).expect("move bench directory");
+        index.rename_tree(&from, &to);
+        moved.push((from, to, snapshot));
+    }
+
+    let walk = time(&moved, |from, snapshot| {
+        resolve_by_walk(from, snapshot, &base, |_| false)
+    });
+    let lookup = time(&moved, |from, snapshot| {
+        resolve_move(from, snapshot, &index, &base, |_| false, |_| true)
+    });
+    println!("walk:   {:?} per move", walk);
+    println!("index:  {:?} per move", lookup);
+    println!(
+        "index is {:.0}x faster",
+        walk.as_secs_f64() / lookup.as_secs_f64().max(f64::EPSILON)
+    );
+
+    let _ = fs::remove_dir_all(&base);
+}
+
+/// Average time `resolve` takes per moved directory, checking that every
+/// one is found where it went.
+fn time(
+    moved: &[(PathBuf, PathBuf, DirSnapshot)],
+    resolve: impl Fn(&Path, &DirSnapshot) -> MoveResolution,
+) -> Duration {
+    let started = Instant::now();
+    for (from, to, snapshot) in moved {
+        let resolution = resolve(from, snapshot);
+        assert_eq!(resolution.new_path(), Some(to.as_path()));
+    }
+    started.elapsed() / moved.len() as u32
+}
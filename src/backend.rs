@@ -0,0 +1,112 @@
+use notify::{Config, EventHandler, PollWatcher, RecommendedWatcher, Watcher};
+use serde::Deserialize;
+use std::{fmt, path::Path, str::FromStr, time::Duration};
+
+/// How changes under a root are picked up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    /// Native on local filesystems, polling on network mounts
+    #[default]
+    Auto,
+    /// Rescan the tree every poll interval; works on any filesystem
+    Poll,
+    /// Kernel notifications (inotify on Linux); local filesystems only
+    Native,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Backend::Auto => "auto",
+            Backend::Poll => "poll",
+            Backend::Native => "native",
+        })
+    }
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Backend, String> {
+        match s {
+            "auto" => Ok(Backend::Auto),
+            "poll" => Ok(Backend::Poll),
+            "native" => Ok(Backend::Native),
+            _ => Err(format!(
+                "unknown backend '{}', expected auto, poll or native",
+                s
+            )),
+        }
+    }
+}
+
+impl Backend {
+    /// Replaces `Auto` with the backend suited to the filesystem at `path`.
+    pub fn resolve(self, path: &Path) -> Backend {
+        match self {
+            Backend::Auto if is_network_filesystem(path) => Backend::Poll,
+            Backend::Auto => Backend::Native,
+            other => other,
+        }
+    }
+
+    /// Creates a watcher of this kind delivering events to `handler`.
+    ///
+    /// `Auto` must have been resolved first.
+    pub(crate) fn create_watcher<F: EventHandler>(
+        self,
+        poll_interval: Duration,
+        handler: F,
+    ) -> notify::Result<Box<dyn Watcher>> {
+        match self {
+            Backend::Native => {
+                let watcher = RecommendedWatcher::new(handler, Config::default())?;
+                Ok(Box::new(watcher))
+            }
+            Backend::Poll | Backend::Auto => {
+                let config = Config::default().with_poll_interval(poll_interval);
+                Ok(Box::new(PollWatcher::new(handler, config)?))
+            }
+        }
+    }
+}
+
+/// Returns true when `path` lives on a filesystem whose changes may be made
+/// by other hosts, which kernel notifications never see.
+#[cfg(target_os = "linux")]
+fn is_network_filesystem(path: &Path) -> bool {
+    use std::{ffi::CString, os::unix::ffi::OsStrExt};
+
+    const NETWORK_MAGIC: &[u32] = &[
+        0x6969,     // NFS
+        0x517b,     // SMB
+        0xff534d42, // CIFS
+        0xfe534d42, // SMB2
+        0x65735546, // FUSE (sshfs, rclone, ...)
+        0x73757245, // Coda
+        0x5346414f, // AFS
+        0x01021997, // 9P
+        0x00c36400, // Ceph
+        0x0bd00bd0, // Lustre
+        0x01161970, // GFS2
+        0x7461636f, // OCFS2
+    ];
+
+    let Ok(c_path) = CString::new(path.as_os_str().as_bytes()) else {
+        return true;
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    // SAFETY: `c_path` is NUL-terminated and `stat` is a valid out-pointer.
+    if unsafe { libc::statfs(c_path.as_ptr(), &mut stat) } != 0 {
+        // When in doubt, polling is the backend that always works
+        return true;
+    }
+    NETWORK_MAGIC.contains(&(stat.f_type as u32))
+}
+
+/// Without a cheap way to tell network shares apart, keep polling.
+#[cfg(not(target_os = "linux"))]
+fn is_network_filesystem(_path: &Path) -> bool {
+    true
+}
@@ -0,0 +1,180 @@
+use crate::{
+    clock::{TimestampFormat, Zone},
+    config::check_directory,
+    log::LogFormat,
+};
+use clap::{Args, Parser, Subcommand};
+use dirmon::{Backend, Depth};
+use std::path::PathBuf;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "dirmon",
+    version,
+    about = "Monitor a directory and log when folders are moved or deleted",
+    args_conflicts_with_subcommands = true
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    // Running `dirmon` without a subcommand behaves like `dirmon watch`
+    #[command(flatten)]
+    pub watch: WatchArgs,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Watch a directory and log folder creations, moves and removals
+    Watch(WatchArgs),
+    /// Move a folder out of the trash back to where it was deleted from
+    Untrash(UntrashArgs),
+    /// Put back a removed folder kept in a shadow directory, or list them
+    Restore(RestoreArgs),
+    /// Show where a folder has been, by a path it had or its id
+    History(HistoryArgs),
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct UntrashArgs {
+    /// The folder in the trash's files directory, or its .trashinfo file
+    #[arg(value_name = "PATH")]
+    pub path: PathBuf,
+}
+
+#[derive(Args, Debug, Clone, Default)]
+pub struct WatchArgs {
+    /// Directory to monitor [default: ./]
+    #[arg(value_name = "ROOT", value_parser = parse_watch_root, conflicts_with = "config")]
+    pub root: Option<PathBuf>,
+
+    /// TOML file listing the roots to monitor
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Seconds between polls of the watched tree [default: 60]
+    #[arg(
+        short = 'i',
+        long = "interval",
+        value_name = "SECS",
+        value_parser = clap::value_parser!(u64).range(1..)
+    )]
+    pub poll_interval: Option<u64>,
+
+    /// Levels of directories below the root to track, or unlimited
+    /// [default: 1]
+    #[arg(short, long, value_name = "LEVELS")]
+    pub depth: Option<Depth>,
+
+    /// How changes are detected: auto, poll or native [default: auto]
+    #[arg(short, long, value_name = "BACKEND")]
+    pub backend: Option<Backend>,
+
+    /// Directory outside the root to look in for folders that vanish from
+    /// it, such as another share or an archive; may be repeated
+    #[arg(long = "search-root", value_name = "DIR")]
+    pub search_roots: Vec<PathBuf>,
+
+    /// Keep a hardlinked mirror of the root in DIR, on the same
+    /// filesystem, so removed folders can be restored
+    #[arg(long = "shadow", value_name = "DIR")]
+    pub shadow_dir: Option<PathBuf>,
+
+    /// File that log entries are appended to [default: dirmon_log.csv]
+    #[arg(short, long, value_name = "FILE")]
+    pub log: Option<PathBuf>,
+
+    /// Layout of log entries [default: csv]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    pub log_format: Option<LogFormat>,
+
+    /// Leave the free-text message column out of CSV logs
+    #[arg(long)]
+    pub no_message_column: bool,
+
+    /// Also print every event to standard output
+    #[arg(long)]
+    pub stdout: bool,
+
+    /// File the known directories are saved to between runs [default: dirmon_state.json]
+    #[arg(long = "state", value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// File that entries are spilled to while a sink keeps failing
+    /// [default: dirmon_fallback.jsonl]
+    #[arg(long = "fallback", value_name = "FILE")]
+    pub fallback_file: Option<PathBuf>,
+
+    /// Timezone of log timestamps: an IANA name, UTC, local or an offset
+    /// like -05:00 [default: America/New_York]
+    #[arg(
+        long,
+        alias = "utc-offset",
+        value_name = "ZONE",
+        allow_hyphen_values = true
+    )]
+    pub timezone: Option<Zone>,
+
+    /// Layout of log timestamps: rfc3339, iso8601 or a strftime string
+    /// [default: "%Y-%m-%d %H:%M:%S %z"]
+    #[arg(long, value_name = "FORMAT")]
+    pub timestamp_format: Option<TimestampFormat>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct RestoreArgs {
+    /// Snapshot to restore, as given in the log; lists the snapshots if
+    /// left out
+    #[arg(value_name = "ID")]
+    pub id: Option<String>,
+
+    /// Shadow directory holding the snapshots
+    #[arg(
+        long = "shadow",
+        value_name = "DIR",
+        required_unless_present = "config"
+    )]
+    pub shadow_dir: Option<PathBuf>,
+
+    /// Config file whose shadow directories are searched
+    #[arg(short, long, value_name = "FILE", conflicts_with = "shadow_dir")]
+    pub config: Option<PathBuf>,
+
+    /// Where to put the folder [default: where it was removed from]
+    #[arg(long, value_name = "PATH", requires = "id")]
+    pub to: Option<PathBuf>,
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct HistoryArgs {
+    /// Any path the folder has had, or its id as shown by an earlier query
+    #[arg(value_name = "PATH_OR_ID")]
+    pub query: String,
+
+    /// File the monitor saves its state to [default: dirmon_state.json]
+    #[arg(long = "state", value_name = "FILE")]
+    pub state_file: Option<PathBuf>,
+
+    /// Config file naming the state file and timezone
+    #[arg(short, long, value_name = "FILE")]
+    pub config: Option<PathBuf>,
+
+    /// Timezone to show times in [default: America/New_York]
+    #[arg(
+        long,
+        alias = "utc-offset",
+        value_name = "ZONE",
+        allow_hyphen_values = true
+    )]
+    pub timezone: Option<Zone>,
+
+    /// Layout of the times shown [default: "%Y-%m-%d %H:%M:%S %z"]
+    #[arg(long, value_name = "FORMAT")]
+    pub timestamp_format: Option<TimestampFormat>,
+}
+
+fn parse_watch_root(s: &str) -> Result<PathBuf, String> {
+    let path = PathBuf::from(s);
+    check_directory(&path)?;
+    Ok(path)
+}
@@ -0,0 +1,136 @@
+use chrono::{DateTime, FixedOffset, Local, SecondsFormat, TimeZone, Utc};
+use chrono_tz::Tz;
+use std::{fmt::Display, str::FromStr};
+
+/// Layout of the timestamp in CSV and stdout entries when none is chosen.
+pub const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S %z";
+
+/// The timezone log timestamps are given in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Zone {
+    Utc,
+    /// The zone of the system dirmon runs on
+    Local,
+    /// An IANA zone such as `America/New_York`, following its DST rules
+    Named(Tz),
+    /// A fixed offset from UTC, never adjusted for DST
+    Fixed(FixedOffset),
+}
+
+impl FromStr for Zone {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Zone, String> {
+        match s {
+            "UTC" | "utc" | "Z" => Ok(Zone::Utc),
+            "local" => Ok(Zone::Local),
+            _ if s.starts_with(['+', '-']) => parse_utc_offset(s).map(Zone::Fixed),
+            _ => s.parse().map(Zone::Named).map_err(|_| {
+                format!(
+                    "unknown timezone '{}', expected an IANA name such as \
+                     America/New_York, UTC, local or an offset like -05:00",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// How timestamps are written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TimestampFormat {
+    /// `2026-03-08T14:30:00-04:00`
+    Rfc3339,
+    /// `2026-03-08T14:30:00.000-04:00`, with milliseconds
+    Iso8601,
+    /// A strftime string, e.g. `%d/%m/%Y %H:%M %Z`
+    Custom(String),
+}
+
+impl Default for TimestampFormat {
+    fn default() -> TimestampFormat {
+        TimestampFormat::Custom(DEFAULT_TIMESTAMP_FORMAT.to_string())
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TimestampFormat, String> {
+        match s {
+            "rfc3339" => Ok(TimestampFormat::Rfc3339),
+            "iso8601" => Ok(TimestampFormat::Iso8601),
+            _ => {
+                let invalid = chrono::format::StrftimeItems::new(s)
+                    .any(|item| item == chrono::format::Item::Error);
+                if invalid {
+                    return Err(format!(
+                        "invalid timestamp format '{}', expected rfc3339, iso8601 \
+                         or a strftime string",
+                        s
+                    ));
+                }
+                Ok(TimestampFormat::Custom(s.to_string()))
+            }
+        }
+    }
+}
+
+/// Stamps entries with the time in the configured zone and format.
+#[derive(Debug, Clone)]
+pub struct Clock {
+    pub zone: Zone,
+    pub format: TimestampFormat,
+}
+
+impl Clock {
+    /// The current time, and the same time written out in the configured
+    /// format.
+    pub fn now(&self) -> (DateTime<FixedOffset>, String) {
+        self.at(Utc::now())
+    }
+
+    /// `time` in the configured zone, and written out in the configured
+    /// format.
+    pub fn at(&self, time: DateTime<Utc>) -> (DateTime<FixedOffset>, String) {
+        match self.zone {
+            Zone::Utc => self.stamp(time),
+            Zone::Local => self.stamp(time.with_timezone(&Local)),
+            Zone::Named(tz) => self.stamp(time.with_timezone(&tz)),
+            Zone::Fixed(offset) => self.stamp(time.with_timezone(&offset)),
+        }
+    }
+
+    fn stamp<T: TimeZone>(&self, time: DateTime<T>) -> (DateTime<FixedOffset>, String)
+    where
+        T::Offset: Display,
+    {
+        let text = match &self.format {
+            TimestampFormat::Rfc3339 => time.to_rfc3339_opts(SecondsFormat::Secs, true),
+            TimestampFormat::Iso8601 => time.format("%Y-%m-%dT%H:%M:%S%.3f%:z").to_string(),
+            TimestampFormat::Custom(format) => time.format(format).to_string(),
+        };
+        (time.fixed_offset(), text)
+    }
+}
+
+fn parse_utc_offset(s: &str) -> Result<FixedOffset, String> {
+    let invalid = || format!("invalid UTC offset '{}', expected [+-]HH:MM", s);
+
+    let (sign, rest) = match s.as_bytes().first() {
+        Some(b'+') => (1, &s[1..]),
+        Some(b'-') => (-1, &s[1..]),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes) = match rest.split_once(':') {
+        Some((h, m)) => (h, m),
+        None => (rest, "0"),
+    };
+    let hours: i32 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i32 = minutes.parse().map_err(|_| invalid())?;
+    if minutes >= 60 {
+        return Err(invalid());
+    }
+
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60)).ok_or_else(invalid)
+}
@@ -0,0 +1,545 @@
+use crate::{
+    cli::{HistoryArgs, WatchArgs},
+    clock::{Clock, TimestampFormat, Zone},
+    log::{FileSink, LogFormat},
+    sink::{SinkKind, SinkSettings},
+};
+use dirmon::{
+    filter::{DEFAULT_IGNORE, DEFAULT_PLACEHOLDERS},
+    Backend, Depth, Filter, Root,
+};
+use serde::Deserialize;
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const DEFAULT_ROOT: &str = "./";
+const DEFAULT_POLL_INTERVAL: u64 = 60;
+const DEFAULT_LOG: &str = "dirmon_log.csv";
+const DEFAULT_TIMEZONE: Zone = Zone::Named(chrono_tz::America::New_York);
+const DEFAULT_STATE_FILE: &str = "dirmon_state.json";
+const DEFAULT_FALLBACK_FILE: &str = "dirmon_fallback.jsonl";
+const DEFAULT_SYSLOG_FACILITY: &str = "daemon";
+const DEFAULT_WEBHOOK_TIMEOUT: u64 = 10;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Layout of the TOML configuration file.
+///
+/// Top-level keys are defaults for every `[[root]]` table; a root that sets
+/// the same key overrides them.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+struct ConfigFile {
+    backend: Option<Backend>,
+    poll_interval: Option<u64>,
+    depth: Option<DepthConfig>,
+    log: Option<PathBuf>,
+    log_format: Option<LogFormat>,
+    csv_message: Option<bool>,
+    timezone: Option<String>,
+    /// Older name of `timezone`, from when only fixed offsets were supported
+    utc_offset: Option<String>,
+    timestamp_format: Option<String>,
+    state_file: Option<PathBuf>,
+    fallback_file: Option<PathBuf>,
+    ignore: Option<Vec<String>>,
+    placeholders: Option<Vec<String>>,
+    search_roots: Option<Vec<PathBuf>>,
+    detect_trash: Option<bool>,
+    shadow_dir: Option<PathBuf>,
+    shadow_retention_days: Option<u64>,
+    manifest_entries: Option<usize>,
+    rename_window: Option<u64>,
+    #[serde(default, rename = "root")]
+    roots: Vec<RootConfig>,
+    #[serde(default, rename = "sink")]
+    sinks: Vec<SinkConfig>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct RootConfig {
+    path: PathBuf,
+    name: Option<String>,
+    backend: Option<Backend>,
+    poll_interval: Option<u64>,
+    depth: Option<DepthConfig>,
+    log: Option<PathBuf>,
+    log_format: Option<LogFormat>,
+    csv_message: Option<bool>,
+    ignore: Option<Vec<String>>,
+    placeholders: Option<Vec<String>>,
+    search_path: Option<PathBuf>,
+    search_roots: Option<Vec<PathBuf>>,
+    detect_trash: Option<bool>,
+    shadow_dir: Option<PathBuf>,
+    shadow_retention_days: Option<u64>,
+    manifest_entries: Option<usize>,
+    rename_window: Option<u64>,
+}
+
+/// `depth` as written in the config file: a number of levels or
+/// "unlimited".
+#[derive(Deserialize, Debug, Clone)]
+#[serde(untagged)]
+enum DepthConfig {
+    Levels(usize),
+    Named(String),
+}
+
+impl DepthConfig {
+    fn resolve(self) -> Result<Depth, String> {
+        match self {
+            DepthConfig::Levels(levels) => levels.to_string().parse(),
+            DepthConfig::Named(name) => name.parse(),
+        }
+    }
+}
+
+/// An output of the config file. Every sink receives the events of all
+/// roots unless `roots` names the ones it is for.
+#[derive(Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+enum SinkConfig {
+    Csv {
+        path: PathBuf,
+        message: Option<bool>,
+        roots: Option<Vec<String>>,
+    },
+    Jsonl {
+        path: PathBuf,
+        roots: Option<Vec<String>>,
+    },
+    Stdout {
+        roots: Option<Vec<String>>,
+    },
+    Syslog {
+        facility: Option<String>,
+        server: Option<String>,
+        roots: Option<Vec<String>>,
+    },
+    Webhook {
+        url: String,
+        timeout: Option<u64>,
+        roots: Option<Vec<String>>,
+    },
+}
+
+impl RootConfig {
+    fn new(path: PathBuf) -> RootConfig {
+        RootConfig {
+            path,
+            name: None,
+            backend: None,
+            poll_interval: None,
+            depth: None,
+            log: None,
+            log_format: None,
+            csv_message: None,
+            ignore: None,
+            placeholders: None,
+            search_path: None,
+            search_roots: None,
+            detect_trash: None,
+            shadow_dir: None,
+            shadow_retention_days: None,
+            manifest_entries: None,
+            rename_window: None,
+        }
+    }
+}
+
+/// Values used for any key a `[[root]]` table leaves unset.
+struct RootDefaults {
+    backend: Backend,
+    poll_interval: u64,
+    depth: Depth,
+    /// Whether roots that do not set `log` write a log file
+    log_enabled: bool,
+    log: PathBuf,
+    log_format: LogFormat,
+    csv_message: bool,
+    ignore: Vec<String>,
+    placeholders: Vec<String>,
+    search_roots: Vec<PathBuf>,
+    detect_trash: bool,
+    shadow_dir: Option<PathBuf>,
+    shadow_retention_days: Option<u64>,
+    manifest_entries: usize,
+    rename_window: Option<u64>,
+}
+
+/// Fully resolved settings for one run of the monitor.
+#[derive(Debug)]
+pub struct Settings {
+    /// Timezone and format of log timestamps
+    pub clock: Clock,
+    /// Where known directories are saved between runs
+    pub state_file: PathBuf,
+    /// Where entries go that a failing sink cannot buffer any more
+    pub fallback_file: PathBuf,
+    pub roots: Vec<Root>,
+    pub sinks: Vec<SinkSettings>,
+}
+
+/// Settings for a single watched root.
+#[derive(Debug, Clone)]
+struct RootSettings {
+    name: String,
+    /// What the library monitors
+    root: Root,
+    /// The log file set by the `log` keys, if it is written
+    log: Option<FileSink>,
+}
+
+impl Settings {
+    /// Builds settings from the command line, reading `--config` if given.
+    ///
+    /// Flags given on the command line override the top-level keys of the
+    /// config file, but not values set on an individual root. The log file
+    /// is written unless `[[sink]]` tables are given and `log` is not.
+    pub fn from_args(args: &WatchArgs) -> Result<Settings, String> {
+        let file = match &args.config {
+            Some(path) => load_config_file(path)?,
+            None => ConfigFile::default(),
+        };
+
+        let clock = resolve_clock(args.timezone, args.timestamp_format.as_ref(), &file)?;
+        let state_file = args
+            .state_file
+            .clone()
+            .or(file.state_file)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE));
+        let fallback_file = args
+            .fallback_file
+            .clone()
+            .or(file.fallback_file)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_FALLBACK_FILE));
+        let defaults = RootDefaults {
+            backend: args.backend.or(file.backend).unwrap_or_default(),
+            poll_interval: args
+                .poll_interval
+                .or(file.poll_interval)
+                .unwrap_or(DEFAULT_POLL_INTERVAL),
+            depth: match (args.depth, file.depth) {
+                (Some(depth), _) => depth,
+                (None, Some(depth)) => depth.resolve()?,
+                (None, None) => Depth::default(),
+            },
+            log_enabled: file.sinks.is_empty() || args.log.is_some() || file.log.is_some(),
+            log: args
+                .log
+                .clone()
+                .or(file.log)
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_LOG)),
+            log_format: args.log_format.or(file.log_format).unwrap_or_default(),
+            csv_message: !args.no_message_column && file.csv_message.unwrap_or(true),
+            ignore: file
+                .ignore
+                .unwrap_or_else(|| DEFAULT_IGNORE.iter().map(|s| s.to_string()).collect()),
+            placeholders: file
+                .placeholders
+                .unwrap_or_else(|| DEFAULT_PLACEHOLDERS.iter().map(|s| s.to_string()).collect()),
+            search_roots: if args.search_roots.is_empty() {
+                file.search_roots.unwrap_or_default()
+            } else {
+                args.search_roots.clone()
+            },
+            detect_trash: file.detect_trash.unwrap_or(true),
+            shadow_dir: args.shadow_dir.clone().or(file.shadow_dir),
+            shadow_retention_days: file.shadow_retention_days,
+            manifest_entries: file.manifest_entries.unwrap_or(0),
+            rename_window: file.rename_window,
+        };
+
+        let root_configs = if args.config.is_some() {
+            if file.roots.is_empty() {
+                return Err("config file does not define any [[root]] tables".to_string());
+            }
+            file.roots
+        } else {
+            let path = args
+                .root
+                .clone()
+                .unwrap_or_else(|| PathBuf::from(DEFAULT_ROOT));
+            vec![RootConfig::new(path)]
+        };
+        let roots = root_configs
+            .into_iter()
+            .map(|root| resolve_root(root, &defaults))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut names = HashSet::new();
+        for root in &roots {
+            if !names.insert(root.name.as_str()) {
+                return Err(format!("root name '{}' is used more than once", root.name));
+            }
+        }
+
+        let mut sinks = log_file_sinks(&roots)?;
+        for sink in file.sinks {
+            sinks.push(resolve_sink(sink, &names)?);
+        }
+        if args.stdout {
+            sinks.push(SinkSettings {
+                kind: SinkKind::Stdout,
+                roots: None,
+            });
+        }
+        check_log_files(&sinks)?;
+
+        Ok(Settings {
+            clock,
+            state_file,
+            fallback_file,
+            roots: roots.into_iter().map(|root| root.root).collect(),
+            sinks,
+        })
+    }
+}
+
+/// One sink per distinct log file, fed by the roots that log to it.
+fn log_file_sinks(roots: &[RootSettings]) -> Result<Vec<SinkSettings>, String> {
+    let mut logs: Vec<(FileSink, HashSet<String>)> = Vec::new();
+    for root in roots {
+        let Some(log) = &root.log else {
+            continue;
+        };
+        match logs.iter_mut().find(|(sink, _)| sink.path == log.path) {
+            Some((sink, names)) if sink == log => {
+                names.insert(root.name.clone());
+            }
+            Some(_) => {
+                return Err(format!(
+                    "log {} is shared by roots with different log formats",
+                    log.path.display()
+                ))
+            }
+            None => logs.push((log.clone(), HashSet::from([root.name.clone()]))),
+        }
+    }
+    Ok(logs
+        .into_iter()
+        .map(|(sink, names)| SinkSettings {
+            kind: SinkKind::File(sink),
+            roots: Some(names),
+        })
+        .collect())
+}
+
+/// Fails if two sinks write the same file, which would interleave their
+/// entries and headers.
+fn check_log_files(sinks: &[SinkSettings]) -> Result<(), String> {
+    let mut paths = HashSet::new();
+    for sink in sinks {
+        let SinkKind::File(file) = &sink.kind else {
+            continue;
+        };
+        let path = std::path::absolute(&file.path).unwrap_or_else(|_| file.path.clone());
+        if !paths.insert(path) {
+            return Err(format!(
+                "{} is written by more than one sink",
+                file.path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+fn resolve_sink(sink: SinkConfig, names: &HashSet<&str>) -> Result<SinkSettings, String> {
+    let (kind, roots) = match sink {
+        SinkConfig::Csv {
+            path,
+            message,
+            roots,
+        } => {
+            let file = FileSink {
+                path,
+                format: LogFormat::Csv,
+                csv_message: message.unwrap_or(true),
+            };
+            (SinkKind::File(file), roots)
+        }
+        SinkConfig::Jsonl { path, roots } => {
+            let file = FileSink {
+                path,
+                format: LogFormat::Jsonl,
+                csv_message: false,
+            };
+            (SinkKind::File(file), roots)
+        }
+        SinkConfig::Stdout { roots } => (SinkKind::Stdout, roots),
+        SinkConfig::Syslog {
+            facility,
+            server,
+            roots,
+        } => {
+            let facility = facility.unwrap_or_else(|| DEFAULT_SYSLOG_FACILITY.to_string());
+            (SinkKind::Syslog { facility, server }, roots)
+        }
+        SinkConfig::Webhook {
+            url,
+            timeout,
+            roots,
+        } => {
+            let timeout = Duration::from_secs(timeout.unwrap_or(DEFAULT_WEBHOOK_TIMEOUT));
+            (SinkKind::Webhook { url, timeout }, roots)
+        }
+    };
+
+    if let Some(unknown) = roots.iter().flatten().find(|r| !names.contains(r.as_str())) {
+        return Err(format!("sink refers to unknown root '{}'", unknown));
+    }
+    Ok(SinkSettings {
+        kind,
+        roots: roots.map(|roots| roots.into_iter().collect()),
+    })
+}
+
+/// Fails with a readable message unless `path` is an accessible directory.
+pub fn check_directory(path: &Path) -> Result<(), String> {
+    match std::fs::metadata(path) {
+        Ok(meta) if meta.is_dir() => Ok(()),
+        Ok(_) => Err(format!("{} is not a directory", path.display())),
+        Err(e) => Err(format!("cannot access {}: {}", path.display(), e)),
+    }
+}
+
+fn load_config_file(path: &Path) -> Result<ConfigFile, String> {
+    let text = std::fs::read_to_string(path)
+        .map_err(|e| format!("cannot read config {}: {}", path.display(), e))?;
+    toml::from_str(&text).map_err(|e| format!("invalid config {}: {}", path.display(), e))
+}
+
+fn resolve_root(root: RootConfig, defaults: &RootDefaults) -> Result<RootSettings, String> {
+    let path = root.path;
+    check_directory(&path)?;
+    let poll_interval = root.poll_interval.unwrap_or(defaults.poll_interval);
+    if poll_interval == 0 {
+        return Err(format!(
+            "poll_interval for {} must be at least 1 second",
+            path.display()
+        ));
+    }
+    let depth = match root.depth {
+        Some(depth) => depth.resolve()?,
+        None => defaults.depth,
+    };
+    let search_path = match root.search_path {
+        Some(search) => {
+            check_directory(&search)?;
+            search
+        }
+        None => path.clone(),
+    };
+
+    let shadow_retention_days = root
+        .shadow_retention_days
+        .or(defaults.shadow_retention_days);
+    if shadow_retention_days == Some(0) {
+        return Err(format!(
+            "shadow_retention_days for {} must be at least 1",
+            path.display()
+        ));
+    }
+
+    let name = root.name.unwrap_or_else(|| path.display().to_string());
+    let ignore =
+        Filter::new(root.ignore.as_ref().unwrap_or(&defaults.ignore)).map_err(|e| e.to_string())?;
+    let placeholders = Filter::new(root.placeholders.as_ref().unwrap_or(&defaults.placeholders))
+        .map_err(|e| e.to_string())?;
+
+    let log = (root.log.is_some() || defaults.log_enabled).then(|| FileSink {
+        path: root.log.unwrap_or_else(|| defaults.log.clone()),
+        format: root.log_format.unwrap_or(defaults.log_format),
+        csv_message: root.csv_message.unwrap_or(defaults.csv_message),
+    });
+
+    let mut library_root = Root::new(path)
+        .name(name.clone())
+        .backend(root.backend.unwrap_or(defaults.backend))
+        .poll_interval(Duration::from_secs(poll_interval))
+        .depth(depth)
+        .ignore(ignore)
+        .placeholders(placeholders)
+        .search_path(search_path)
+        .search_roots(
+            root.search_roots
+                .unwrap_or_else(|| defaults.search_roots.clone()),
+        )
+        .detect_trash(root.detect_trash.unwrap_or(defaults.detect_trash))
+        .manifest_entries(root.manifest_entries.unwrap_or(defaults.manifest_entries));
+    if let Some(dir) = root.shadow_dir.or_else(|| defaults.shadow_dir.clone()) {
+        library_root = library_root.shadow(dir);
+    }
+    if let Some(secs) = root.rename_window.or(defaults.rename_window) {
+        library_root = library_root.rename_window(Duration::from_secs(secs));
+    }
+    if let Some(days) = shadow_retention_days {
+        library_root = library_root.shadow_retention(Duration::from_secs(days * SECONDS_PER_DAY));
+    }
+
+    Ok(RootSettings {
+        name,
+        log,
+        root: library_root,
+    })
+}
+
+/// The clock given on the command line or, failing that, by the top-level
+/// keys of the config file.
+fn resolve_clock(
+    zone: Option<Zone>,
+    format: Option<&TimestampFormat>,
+    file: &ConfigFile,
+) -> Result<Clock, String> {
+    let zone = match (zone, file.timezone.as_ref().or(file.utc_offset.as_ref())) {
+        (Some(zone), _) => zone,
+        (None, Some(zone)) => zone.parse()?,
+        (None, None) => DEFAULT_TIMEZONE,
+    };
+    let format = match (format, &file.timestamp_format) {
+        (Some(format), _) => format.clone(),
+        (None, Some(format)) => format.parse()?,
+        (None, None) => TimestampFormat::default(),
+    };
+    Ok(Clock { zone, format })
+}
+
+/// The state file to read lineages from and the clock to show their times
+/// with, from the command line and the config file if one is given.
+pub fn history_settings(args: &HistoryArgs) -> Result<(PathBuf, Clock), String> {
+    let file = match &args.config {
+        Some(path) => load_config_file(path)?,
+        None => ConfigFile::default(),
+    };
+    let clock = resolve_clock(args.timezone, args.timestamp_format.as_ref(), &file)?;
+    let state_file = args
+        .state_file
+        .clone()
+        .or(file.state_file)
+        .unwrap_or_else(|| PathBuf::from(DEFAULT_STATE_FILE));
+    Ok((state_file, clock))
+}
+
+/// The shadow directories named in the config file at `path`, for finding
+/// snapshots to restore.
+pub fn shadow_dirs(path: &Path) -> Result<Vec<PathBuf>, String> {
+    let file = load_config_file(path)?;
+    let mut dirs: Vec<PathBuf> = Vec::new();
+    let named = file
+        .shadow_dir
+        .into_iter()
+        .chain(file.roots.into_iter().filter_map(|root| root.shadow_dir));
+    for dir in named {
+        if !dirs.contains(&dir) {
+            dirs.push(dir);
+        }
+    }
+    if dirs.is_empty() {
+        return Err(format!("config {} sets no shadow_dir", path.display()));
+    }
+    Ok(dirs)
+}
@@ -0,0 +1,37 @@
+use std::{fmt, io, path::PathBuf};
+
+/// Errors raised while setting up a [`DirMonitor`](crate::DirMonitor).
+#[derive(Debug)]
+pub enum Error {
+    /// A filesystem operation on `path` failed
+    Io { path: PathBuf, source: io::Error },
+    /// The watcher backend could not be started
+    Watch {
+        path: PathBuf,
+        source: notify::Error,
+    },
+    /// A setting was rejected
+    Invalid(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => write!(f, "cannot access {}: {}", path.display(), source),
+            Error::Watch { path, source } => {
+                write!(f, "cannot watch {}: {}", path.display(), source)
+            }
+            Error::Invalid(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::Watch { source, .. } => Some(source),
+            Error::Invalid(_) => None,
+        }
+    }
+}
@@ -0,0 +1,208 @@
+use crate::manifest::Manifest;
+use chrono::NaiveDateTime;
+use std::path::{Path, PathBuf};
+
+/// A change to the directories of a watched root.
+///
+/// Every variant carries the name of the root it happened in. `offline` is
+/// set on changes found when comparing saved state with the disk at start,
+/// which happened while no monitor was running. Removals and moves carry
+/// the [`Manifest`] of what the directory held when last seen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DirEvent {
+    /// Monitoring of the root began
+    Started { root: String },
+    /// A directory appeared. `placeholder` is the default name it was
+    /// created under, when it got its real name later.
+    Created {
+        root: String,
+        path: PathBuf,
+        placeholder: Option<PathBuf>,
+        offline: bool,
+    },
+    /// A directory appeared at `to` with nearly the same contents as the
+    /// tracked directory `from`, which is still there
+    Copied {
+        root: String,
+        from: PathBuf,
+        to: PathBuf,
+        offline: bool,
+    },
+    /// A directory vanished and was not found anywhere else. `snapshot` is
+    /// the id under which its contents were kept, when the root has a
+    /// shadow directory.
+    Removed {
+        root: String,
+        path: PathBuf,
+        snapshot: Option<String>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// A directory moved to another parent, possibly under a new name.
+    /// `confidence` is set when it was matched on content, not identity.
+    Moved {
+        root: String,
+        from: PathBuf,
+        to: PathBuf,
+        confidence: Option<f64>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// A directory got a new name in the same parent
+    Renamed {
+        root: String,
+        from: PathBuf,
+        to: PathBuf,
+        confidence: Option<f64>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// A directory left the root for one of its search roots. `confidence`
+    /// is set when it was matched on content, not identity.
+    MovedOut {
+        root: String,
+        from: PathBuf,
+        to: PathBuf,
+        confidence: Option<f64>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// A directory was deleted to a freedesktop.org Trash and is now at
+    /// `trashed_to`. `deleted_at` is the deletion time the trash recorded,
+    /// in the deleting machine's local time.
+    Trashed {
+        root: String,
+        path: PathBuf,
+        trashed_to: PathBuf,
+        deleted_at: Option<NaiveDateTime>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// A directory vanished and several directories could be where it went,
+    /// best candidate first
+    AmbiguousMove {
+        root: String,
+        from: PathBuf,
+        candidates: Vec<(PathBuf, f64)>,
+        manifest: Option<Manifest>,
+        offline: bool,
+    },
+    /// The watcher or the monitor itself ran into a problem
+    Error { root: String, message: String },
+}
+
+impl DirEvent {
+    /// A move or rename of `from` to `to`, depending on whether the parent
+    /// changed.
+    pub(crate) fn moved(
+        root: &str,
+        from: &Path,
+        to: &Path,
+        confidence: Option<f64>,
+        offline: bool,
+    ) -> DirEvent {
+        let (root, from, to) = (root.to_string(), from.to_path_buf(), to.to_path_buf());
+        if from.parent() == to.parent() {
+            DirEvent::Renamed {
+                root,
+                from,
+                to,
+                confidence,
+                manifest: None,
+                offline,
+            }
+        } else {
+            DirEvent::Moved {
+                root,
+                from,
+                to,
+                confidence,
+                manifest: None,
+                offline,
+            }
+        }
+    }
+
+    /// Attaches `manifest` to a removal or move; other events are returned
+    /// as they are.
+    pub(crate) fn with_manifest(mut self, new: Option<Manifest>) -> DirEvent {
+        match &mut self {
+            DirEvent::Removed { manifest, .. }
+            | DirEvent::Moved { manifest, .. }
+            | DirEvent::Renamed { manifest, .. }
+            | DirEvent::MovedOut { manifest, .. }
+            | DirEvent::Trashed { manifest, .. }
+            | DirEvent::AmbiguousMove { manifest, .. } => *manifest = new,
+            DirEvent::Started { .. }
+            | DirEvent::Created { .. }
+            | DirEvent::Copied { .. }
+            | DirEvent::Error { .. } => {}
+        }
+        self
+    }
+
+    /// What a removed or moved directory held when it was last seen.
+    pub fn manifest(&self) -> Option<&Manifest> {
+        match self {
+            DirEvent::Removed { manifest, .. }
+            | DirEvent::Moved { manifest, .. }
+            | DirEvent::Renamed { manifest, .. }
+            | DirEvent::MovedOut { manifest, .. }
+            | DirEvent::Trashed { manifest, .. }
+            | DirEvent::AmbiguousMove { manifest, .. } => manifest.as_ref(),
+            DirEvent::Started { .. }
+            | DirEvent::Created { .. }
+            | DirEvent::Copied { .. }
+            | DirEvent::Error { .. } => None,
+        }
+    }
+
+    /// The path a directory left and the path it arrived at, as far as the
+    /// event tells.
+    pub(crate) fn endpoints(&self) -> (Option<&Path>, Option<&Path>) {
+        match self {
+            DirEvent::Created { path, .. } => (None, Some(path)),
+            DirEvent::Copied { to, .. } => (None, Some(to)),
+            DirEvent::Moved { from, to, .. }
+            | DirEvent::Renamed { from, to, .. }
+            | DirEvent::MovedOut { from, to, .. } => (Some(from), Some(to)),
+            DirEvent::Trashed {
+                path, trashed_to, ..
+            } => (Some(path), Some(trashed_to)),
+            DirEvent::Removed { path, .. } => (Some(path), None),
+            DirEvent::AmbiguousMove { from, .. } => (Some(from), None),
+            DirEvent::Started { .. } | DirEvent::Error { .. } => (None, None),
+        }
+    }
+
+    /// Name of the root the event happened in.
+    pub fn root(&self) -> &str {
+        match self {
+            DirEvent::Started { root }
+            | DirEvent::Created { root, .. }
+            | DirEvent::Copied { root, .. }
+            | DirEvent::Removed { root, .. }
+            | DirEvent::Moved { root, .. }
+            | DirEvent::Renamed { root, .. }
+            | DirEvent::MovedOut { root, .. }
+            | DirEvent::Trashed { root, .. }
+            | DirEvent::AmbiguousMove { root, .. }
+            | DirEvent::Error { root, .. } => root,
+        }
+    }
+
+    /// Whether the change happened while no monitor was running.
+    pub fn is_offline(&self) -> bool {
+        match self {
+            DirEvent::Created { offline, .. }
+            | DirEvent::Copied { offline, .. }
+            | DirEvent::Removed { offline, .. }
+            | DirEvent::Moved { offline, .. }
+            | DirEvent::Renamed { offline, .. }
+            | DirEvent::MovedOut { offline, .. }
+            | DirEvent::Trashed { offline, .. }
+            | DirEvent::AmbiguousMove { offline, .. } => *offline,
+            DirEvent::Started { .. } | DirEvent::Error { .. } => false,
+        }
+    }
+}
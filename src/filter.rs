@@ -0,0 +1,212 @@
+use crate::error::Error;
+use globset::{GlobBuilder, GlobMatcher};
+use std::path::{Component, Path};
+
+/// Directories ignored unless configured otherwise: trash folders and the
+/// metadata folders of macOS, Synology and Office.
+pub const DEFAULT_IGNORE: &[&str] = &[".Trash-*", ".DS_Store", "@eaDir", "~$*"];
+
+/// Default names file managers give new folders.
+pub const DEFAULT_PLACEHOLDERS: &[&str] = &[
+    "New folder",
+    "New folder (*)",
+    "untitled folder",
+    "untitled folder *",
+    "Untitled Folder",
+    "Untitled Folder *",
+];
+
+/// Gitignore-style rules matching directories, used to decide which ones
+/// are ignored and which are placeholders.
+///
+/// Rules are checked in order and the last one that matches wins. A rule
+/// starting with `!` takes back what earlier rules matched. A pattern
+/// without a `/` matches a directory name at any depth; one with a `/` is
+/// matched against the path relative to the root. A directory inside a
+/// matching one matches as well.
+#[derive(Debug, Clone, Default)]
+pub struct Filter {
+    rules: Vec<Rule>,
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    matcher: GlobMatcher,
+    /// `!pattern`: matching paths are excluded again
+    include: bool,
+    /// The pattern contains a `/` and is matched against the whole path
+    anchored: bool,
+}
+
+impl Filter {
+    pub fn new<S: AsRef<str>>(patterns: &[S]) -> Result<Filter, Error> {
+        let mut rules = Vec::new();
+        for pattern in patterns {
+            let pattern = pattern.as_ref();
+            let (include, glob) = match pattern.strip_prefix('!') {
+                Some(rest) => (true, rest),
+                None => (false, pattern),
+            };
+            // Only directories are tracked, so a trailing slash changes nothing
+            let glob = glob.trim_end_matches('/');
+            let anchored = glob.contains('/');
+            let glob = glob.trim_start_matches('/');
+            if glob.is_empty() {
+                return Err(Error::Invalid(format!("invalid pattern '{}'", pattern)));
+            }
+            let matcher = GlobBuilder::new(glob)
+                .literal_separator(true)
+                .build()
+                .map_err(|e| Error::Invalid(format!("invalid pattern '{}': {}", pattern, e)))?
+                .compile_matcher();
+            rules.push(Rule {
+                matcher,
+                include,
+                anchored,
+            });
+        }
+        Ok(Filter { rules })
+    }
+
+    /// Rules built from [`DEFAULT_IGNORE`].
+    pub fn default_ignore() -> Filter {
+        Filter::new(DEFAULT_IGNORE).expect("default ignore patterns are valid")
+    }
+
+    /// Rules built from [`DEFAULT_PLACEHOLDERS`].
+    pub fn default_placeholders() -> Filter {
+        Filter::new(DEFAULT_PLACEHOLDERS).expect("default placeholder patterns are valid")
+    }
+
+    /// Returns whether the directory at `relative`, a path below the root,
+    /// or one of its ancestors matches.
+    pub fn matches(&self, relative: &Path) -> bool {
+        if self.rules.is_empty() {
+            return false;
+        }
+        let mut prefix = Path::new("").to_path_buf();
+        for component in relative.components() {
+            let Component::Normal(name) = component else {
+                continue;
+            };
+            prefix.push(name);
+            if self.matches_at(&prefix, Path::new(name)) {
+                return true;
+            }
+        }
+        false
+    }
+
+    fn matches_at(&self, relative: &Path, name: &Path) -> bool {
+        let mut matched = false;
+        for rule in &self.rules {
+            let subject = if rule.anchored { relative } else { name };
+            if rule.matcher.is_match(subject) {
+                matched = !rule.include;
+            }
+        }
+        matched
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(patterns: &[&str], relative: &str) -> bool {
+        Filter::new(patterns).unwrap().matches(Path::new(relative))
+    }
+
+    #[test]
+    fn last_matching_rule_wins() {
+        assert!(!matches(&["build", "!build"], "build"));
+        assert!(matches(&["build", "!build", "build"], "build"));
+        assert!(matches(&["!build", "build"], "build"));
+    }
+
+    #[test]
+    fn negated_rule_takes_back_earlier_matches() {
+        let patterns = ["cache*", "!cache-keep"];
+        assert!(matches(&patterns, "cache-old"));
+        assert!(!matches(&patterns, "cache-keep"));
+        assert!(!matches(&["!cache"], "cache"));
+    }
+
+    #[test]
+    fn pattern_without_slash_matches_names_at_any_depth() {
+        assert!(matches(&["archive"], "archive"));
+        assert!(matches(&["archive"], "clients/acme/archive"));
+        assert!(!matches(&["archive"], "archives"));
+        assert!(matches(&["*.bak"], "clients/old.bak"));
+    }
+
+    #[test]
+    fn pattern_with_slash_matches_from_the_root() {
+        assert!(matches(&["docs/archive"], "docs/archive"));
+        assert!(!matches(&["docs/archive"], "clients/docs/archive"));
+        assert!(!matches(&["docs/archive"], "archive"));
+        assert!(matches(&["/build"], "build"));
+        assert!(!matches(&["/build"], "src/build"));
+        assert!(matches(&["build/"], "src/build"));
+        // A wildcard does not cross a separator
+        assert!(matches(&["clients/*/old"], "clients/acme/old"));
+        assert!(!matches(&["clients/*/old"], "clients/acme/x/old"));
+    }
+
+    #[test]
+    fn directories_inside_a_match_match() {
+        assert!(matches(&["node_modules"], "app/node_modules/pkg/lib"));
+        assert!(matches(&["docs/archive"], "docs/archive/2019"));
+        // As with gitignore, nothing inside a matched directory can be taken
+        // back
+        assert!(matches(&["archive", "!archive/keep"], "archive/keep"));
+        assert!(!matches(&["node_modules"], "app/src"));
+    }
+
+    #[test]
+    fn defaults() {
+        let ignore = Filter::default_ignore();
+        for path in [
+            ".Trash-1000",
+            "share/.DS_Store",
+            "photos/@eaDir/x",
+            "~$report",
+        ] {
+            assert!(ignore.matches(Path::new(path)), "{} is ignored", path);
+        }
+        for path in ["Trash", "photos", "report~$"] {
+            assert!(!ignore.matches(Path::new(path)), "{} is not ignored", path);
+        }
+
+        let placeholders = Filter::default_placeholders();
+        for path in [
+            "New folder",
+            "clients/New folder (2)",
+            "untitled folder",
+            "untitled folder 3",
+            "Untitled Folder",
+            "Untitled Folder 2",
+        ] {
+            assert!(
+                placeholders.matches(Path::new(path)),
+                "{} is a placeholder",
+                path
+            );
+        }
+        for path in ["New folder 2", "new folder", "Acme"] {
+            assert!(
+                !placeholders.matches(Path::new(path)),
+                "{} is not a placeholder",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_patterns_are_rejected() {
+        assert!(Filter::new(&["!"]).is_err());
+        assert!(Filter::new(&["/"]).is_err());
+        assert!(Filter::new(&["[a"]).is_err());
+        assert!(!Filter::default().matches(Path::new("anything")));
+    }
+}
@@ -0,0 +1,224 @@
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Modification times closer than this count as the same, since copies to
+/// FAT and some network filesystems round them to two seconds.
+const MTIME_TOLERANCE: i64 = 2;
+
+/// What the immediate children of a directory look like, by name, size and
+/// modification time.
+///
+/// A directory copied elsewhere and deleted, or moved by something that
+/// does not keep its identity, still has the same fingerprint, whatever it
+/// is now called.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Fingerprint(BTreeMap<String, ChildPrint>);
+
+/// Saved as a `[size, mtime]` pair, which keeps state files small.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "(Option<u64>, Option<i64>)", from = "SavedChildPrint")]
+pub struct ChildPrint {
+    /// Size of a file; `None` for anything else
+    pub size: Option<u64>,
+    /// Modification time in whole seconds since the epoch
+    pub mtime: Option<i64>,
+}
+
+/// A child as saved by any version; older ones wrote out the field names.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum SavedChildPrint {
+    Pair(Option<u64>, Option<i64>),
+    Fields {
+        size: Option<u64>,
+        mtime: Option<i64>,
+    },
+}
+
+impl From<SavedChildPrint> for ChildPrint {
+    fn from(saved: SavedChildPrint) -> ChildPrint {
+        match saved {
+            SavedChildPrint::Pair(size, mtime) | SavedChildPrint::Fields { size, mtime } => {
+                ChildPrint { size, mtime }
+            }
+        }
+    }
+}
+
+impl From<ChildPrint> for (Option<u64>, Option<i64>) {
+    fn from(child: ChildPrint) -> (Option<u64>, Option<i64>) {
+        (child.size, child.mtime)
+    }
+}
+
+impl ChildPrint {
+    pub fn new(size: Option<u64>, mtime: Option<SystemTime>) -> ChildPrint {
+        ChildPrint {
+            size,
+            mtime: mtime.map(|t| match t.duration_since(UNIX_EPOCH) {
+                Ok(after) => after.as_secs() as i64,
+                Err(before) => -(before.duration().as_secs() as i64),
+            }),
+        }
+    }
+
+    /// The modification time, to the second.
+    pub fn modified(&self) -> Option<SystemTime> {
+        self.mtime.map(|secs| match u64::try_from(secs) {
+            Ok(after) => UNIX_EPOCH + Duration::from_secs(after),
+            Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+        })
+    }
+
+    /// How alike two children with the same name are, from 0.25 for the
+    /// name alone to 1.0 for matching size and modification time.
+    fn similarity(&self, other: &ChildPrint) -> f64 {
+        let size = self.size == other.size;
+        let mtime = match (self.mtime, other.mtime) {
+            (Some(a), Some(b)) => (a - b).abs() <= MTIME_TOLERANCE,
+            _ => false,
+        };
+        match (size, mtime) {
+            (true, true) => 1.0,
+            (true, false) => 0.75,
+            (false, true) => 0.5,
+            (false, false) => 0.25,
+        }
+    }
+}
+
+impl Fingerprint {
+    pub fn insert(&mut self, name: String, child: ChildPrint) {
+        self.0.insert(name, child);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.0.contains_key(name)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.0.keys()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&String, &ChildPrint)> {
+        self.0.iter()
+    }
+
+    /// Scores from 0.0 to 1.0 how alike two directories' contents are.
+    /// Children found in only one of them count as not alike at all; two
+    /// empty directories score 0.0, as there is nothing to go on.
+    pub fn similarity(&self, other: &Fingerprint) -> f64 {
+        self.score(other, ChildPrint::similarity)
+    }
+
+    /// Like [`Fingerprint::similarity`], but on names and sizes alone: a
+    /// copy made without keeping times has new ones throughout.
+    pub fn content_similarity(&self, other: &Fingerprint) -> f64 {
+        self.score(other, |a, b| if a.size == b.size { 1.0 } else { 0.5 })
+    }
+
+    fn score(&self, other: &Fingerprint, child: impl Fn(&ChildPrint, &ChildPrint) -> f64) -> f64 {
+        let mut shared = 0.0;
+        let mut total = other.0.len();
+        for (name, ours) in &self.0 {
+            match other.0.get(name) {
+                Some(theirs) => shared += child(ours, theirs),
+                None => total += 1,
+            }
+        }
+        if total == 0 {
+            0.0
+        } else {
+            shared / total as f64
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fingerprint of files given as name, size and modification time.
+    fn fingerprint(files: &[(&str, u64, i64)]) -> Fingerprint {
+        let mut fingerprint = Fingerprint::default();
+        for &(name, size, mtime) in files {
+            let child = ChildPrint {
+                size: Some(size),
+                mtime: Some(mtime),
+            };
+            fingerprint.insert(name.to_string(), child);
+        }
+        fingerprint
+    }
+
+    #[test]
+    fn same_children_score_one() {
+        let files = [("a", 1, 100), ("b", 2, 200)];
+        assert_eq!(fingerprint(&files).similarity(&fingerprint(&files)), 1.0);
+    }
+
+    #[test]
+    fn times_within_the_tolerance_count_as_the_same() {
+        let old = fingerprint(&[("a", 1, 100)]);
+        let rounded = fingerprint(&[("a", 1, 100 + MTIME_TOLERANCE)]);
+        let touched = fingerprint(&[("a", 1, 101 + MTIME_TOLERANCE)]);
+        assert_eq!(old.similarity(&rounded), 1.0);
+        assert_eq!(old.similarity(&touched), 0.75);
+    }
+
+    #[test]
+    fn changed_children_score_less() {
+        let old = fingerprint(&[("a", 1, 100)]);
+        assert_eq!(old.similarity(&fingerprint(&[("a", 2, 100)])), 0.5);
+        assert_eq!(old.similarity(&fingerprint(&[("a", 2, 500)])), 0.25);
+    }
+
+    #[test]
+    fn children_in_one_only_count_against() {
+        let old = fingerprint(&[("a", 1, 100), ("b", 1, 100)]);
+        let new = fingerprint(&[("a", 1, 100), ("c", 1, 100)]);
+        assert_eq!(old.similarity(&new), 1.0 / 3.0);
+        assert_eq!(new.similarity(&old), 1.0 / 3.0);
+    }
+
+    #[test]
+    fn empty_fingerprints_score_zero() {
+        let empty = Fingerprint::default();
+        assert_eq!(empty.similarity(&empty), 0.0);
+        assert_eq!(empty.similarity(&fingerprint(&[("a", 1, 100)])), 0.0);
+    }
+
+    #[test]
+    fn content_similarity_ignores_times() {
+        let old = fingerprint(&[("a", 1, 100), ("b", 2, 100)]);
+        let copy = fingerprint(&[("a", 1, 900), ("b", 2, 900)]);
+        let edited = fingerprint(&[("a", 1, 900), ("b", 3, 900)]);
+        assert_eq!(old.content_similarity(&copy), 1.0);
+        assert_eq!(old.content_similarity(&edited), 0.75);
+        assert_eq!(old.similarity(&copy), 0.75);
+    }
+    #[test]
+    fn children_are_saved_as_pairs_and_read_either_way() {
+        let child = ChildPrint {
+            size: Some(3),
+            mtime: None,
+        };
+        assert_eq!(serde_json::to_string(&child).unwrap(), "[3,null]");
+        let read: ChildPrint = serde_json::from_str("[3,null]").unwrap();
+        assert_eq!(read, child);
+        let read: ChildPrint = serde_json::from_str(r#"{"size":3,"mtime":null}"#).unwrap();
+        assert_eq!(read, child);
+    }
+}
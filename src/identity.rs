@@ -0,0 +1,40 @@
+use serde::{Deserialize, Serialize};
+use std::{fs::Metadata, path::Path, time::SystemTime};
+
+/// Filesystem identity of a directory, which survives moves and renames
+/// within one filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DirIdentity {
+    pub dev: u64,
+    pub ino: u64,
+    /// Birth time, where the filesystem records one. Inode numbers are
+    /// reused once freed, so this tells a recycled inode apart.
+    pub created: Option<SystemTime>,
+}
+
+impl DirIdentity {
+    /// The identity of whatever is at `path` now.
+    pub fn of(path: &Path) -> Option<DirIdentity> {
+        std::fs::metadata(path)
+            .ok()
+            .and_then(|m| DirIdentity::from_metadata(&m))
+    }
+
+    #[cfg(unix)]
+    pub fn from_metadata(meta: &Metadata) -> Option<DirIdentity> {
+        use std::os::unix::fs::MetadataExt;
+
+        Some(DirIdentity {
+            dev: meta.dev(),
+            ino: meta.ino(),
+            created: meta.created().ok(),
+        })
+    }
+
+    /// Stable file IDs are not available here; callers fall back to
+    /// matching on names.
+    #[cfg(not(unix))]
+    pub fn from_metadata(_meta: &Metadata) -> Option<DirIdentity> {
+        None
+    }
+}
@@ -0,0 +1,275 @@
+use crate::identity::DirIdentity;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Every directory under a root, by name and by identity.
+///
+/// Built with one walk at start and then kept current from watcher events,
+/// so a vanished directory is looked up instead of searched for.
+pub struct DirIndex {
+    base: PathBuf,
+    /// A directory below `base` that is left out, with everything in it
+    excluded: Option<PathBuf>,
+    /// Ordered by path, which keeps each subtree in one contiguous range
+    paths: BTreeMap<PathBuf, Option<DirIdentity>>,
+    by_identity: HashMap<DirIdentity, PathBuf>,
+    by_name: HashMap<OsString, HashSet<PathBuf>>,
+}
+
+impl DirIndex {
+    /// Indexes the directories below `base`, except `excluded` and what is
+    /// inside it.
+    pub fn build(base: &Path, excluded: Option<&Path>) -> DirIndex {
+        let mut index = DirIndex {
+            base: base.to_path_buf(),
+            excluded: excluded.map(Path::to_path_buf),
+            paths: BTreeMap::new(),
+            by_identity: HashMap::new(),
+            by_name: HashMap::new(),
+        };
+        index.walk(base, 1);
+        index
+    }
+
+    /// Whether every directory under `path` is in the index.
+    pub fn covers(&self, path: &Path) -> bool {
+        path.starts_with(&self.base) && !self.is_excluded(path)
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excluded.as_ref().is_some_and(|e| path.starts_with(e))
+    }
+
+    pub fn len(&self) -> usize {
+        self.paths.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.paths.is_empty()
+    }
+
+    fn walk(&mut self, path: &Path, min_depth: usize) {
+        let excluded = self.excluded.clone();
+        let dirs = WalkDir::new(path)
+            .min_depth(min_depth)
+            .into_iter()
+            .filter_entry(move |e| !excluded.as_ref().is_some_and(|x| e.path().starts_with(x)))
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_dir());
+        for entry in dirs {
+            let identity = entry
+                .metadata()
+                .ok()
+                .and_then(|m| DirIdentity::from_metadata(&m));
+            self.insert(entry.into_path(), identity);
+        }
+    }
+
+    fn insert(&mut self, path: PathBuf, identity: Option<DirIdentity>) {
+        if let Some(old) = self.paths.insert(path.clone(), identity) {
+            self.forget(&path, old);
+        }
+        if let Some(identity) = identity {
+            self.by_identity.insert(identity, path.clone());
+        }
+        if let Some(name) = path.file_name() {
+            self.by_name
+                .entry(name.to_os_string())
+                .or_default()
+                .insert(path);
+        }
+    }
+
+    /// Drops the lookups pointing at `path`.
+    fn forget(&mut self, path: &Path, identity: Option<DirIdentity>) {
+        if let Some(identity) = identity {
+            if self.by_identity.get(&identity).is_some_and(|p| p == path) {
+                self.by_identity.remove(&identity);
+            }
+        }
+        if let Some(name) = path.file_name() {
+            if let Some(paths) = self.by_name.get_mut(name) {
+                paths.remove(path);
+                if paths.is_empty() {
+                    self.by_name.remove(name);
+                }
+            }
+        }
+    }
+
+    /// Paths in the subtree at `path`, including `path` itself.
+    fn subtree(&self, path: &Path) -> Vec<PathBuf> {
+        self.paths
+            .range(path.to_path_buf()..)
+            .map(|(p, _)| p)
+            .take_while(|p| p.starts_with(path))
+            .cloned()
+            .collect()
+    }
+
+    /// Adds a directory that appeared, with everything inside it.
+    pub fn add_tree(&mut self, path: &Path) {
+        if !self.covers(path) || !path.is_dir() {
+            return;
+        }
+        let identity = DirIdentity::of(path);
+        // Already indexed along with a parent that appeared first
+        if identity.is_some() && self.paths.get(path) == Some(&identity) {
+            return;
+        }
+        self.remove_tree(path);
+        self.walk(path, 0);
+    }
+
+    /// Drops a directory that vanished, with everything that was inside it.
+    pub fn remove_tree(&mut self, path: &Path) {
+        for removed in self.subtree(path) {
+            if let Some(identity) = self.paths.remove(&removed) {
+                self.forget(&removed, identity);
+            }
+        }
+    }
+
+    /// Moves the entries of a renamed directory to its new path.
+    pub fn rename_tree(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<(PathBuf, Option<DirIdentity>)> = self
+            .subtree(from)
+            .into_iter()
+            .filter_map(|old| {
+                let identity = self.paths.remove(&old)?;
+                self.forget(&old, identity);
+                let rest = old.strip_prefix(from).ok()?;
+                Some((to.join(rest), identity))
+            })
+            .collect();
+        if moved.is_empty() {
+            return self.add_tree(to);
+        }
+        self.remove_tree(to);
+        for (path, identity) in moved {
+            if self.covers(&path) {
+                self.insert(path, identity);
+            }
+        }
+    }
+
+    /// Where the directory with `identity` was last seen.
+    pub fn find_identity(&self, identity: &DirIdentity) -> Option<&Path> {
+        self.by_identity.get(identity).map(PathBuf::as_path)
+    }
+
+    /// Directories called `name`.
+    pub fn named(&self, name: &OsStr) -> impl Iterator<Item = &Path> {
+        self.by_name
+            .get(name)
+            .into_iter()
+            .flatten()
+            .map(PathBuf::as_path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    /// A directory of its own holding `dirs`, removed first if a previous
+    /// run left it behind.
+    fn base(name: &str, dirs: &[&str]) -> PathBuf {
+        let base =
+            std::env::temp_dir().join(format!("dirmon-index-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&base);
+        for dir in dirs {
+            fs::create_dir_all(base.join(dir)).unwrap();
+        }
+        base
+    }
+
+    fn indexed(index: &DirIndex, base: &Path) -> Vec<PathBuf> {
+        index
+            .paths
+            .keys()
+            .map(|p| p.strip_prefix(base).unwrap().to_path_buf())
+            .collect()
+    }
+
+    #[test]
+    fn subtree_is_contiguous_next_to_similar_names() {
+        let base = base("subtree", &["a/x/y", "a b", "a-c", "a.d", "ab"]);
+        let index = DirIndex::build(&base, None);
+        let subtree: Vec<PathBuf> = index
+            .subtree(&base.join("a"))
+            .iter()
+            .map(|p| p.strip_prefix(&base).unwrap().to_path_buf())
+            .collect();
+        assert_eq!(subtree, ["a", "a/x", "a/x/y"].map(PathBuf::from));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn rename_tree_moves_the_subtree_only() {
+        let base = base("rename", &["a/x/y", "a b", "ab", "c"]);
+        let mut index = DirIndex::build(&base, None);
+        let y = DirIdentity::of(&base.join("a/x/y")).unwrap();
+        fs::rename(base.join("a"), base.join("c/z")).unwrap();
+        index.rename_tree(&base.join("a"), &base.join("c/z"));
+
+        assert_eq!(
+            indexed(&index, &base),
+            ["a b", "ab", "c", "c/z", "c/z/x", "c/z/x/y"].map(PathBuf::from)
+        );
+        assert_eq!(
+            index.find_identity(&y),
+            Some(base.join("c/z/x/y").as_path())
+        );
+        assert_eq!(
+            index.named(OsStr::new("x")).collect::<Vec<_>>(),
+            [base.join("c/z/x")]
+        );
+        assert_eq!(index.named(OsStr::new("a")).count(), 0);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn rename_tree_of_an_unknown_path_indexes_what_arrived() {
+        let base = base("unknown", &["a"]);
+        let mut index = DirIndex::build(&base, None);
+        fs::create_dir_all(base.join("b/x")).unwrap();
+        index.rename_tree(&base.join("elsewhere"), &base.join("b"));
+        assert_eq!(indexed(&index, &base), ["a", "b", "b/x"].map(PathBuf::from));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn excluded_directory_is_never_indexed() {
+        let base = base("excluded", &["a", "shadow/mirror/a"]);
+        let shadow = base.join("shadow");
+        let mut index = DirIndex::build(&base, Some(&shadow));
+        assert_eq!(indexed(&index, &base), ["a"].map(PathBuf::from));
+
+        fs::rename(base.join("a"), shadow.join("a")).unwrap();
+        index.rename_tree(&base.join("a"), &shadow.join("a"));
+        index.add_tree(&shadow.join("mirror"));
+        assert!(index.is_empty());
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn remove_tree_forgets_names_and_identities() {
+        let base = base("remove", &["a/x", "b/x"]);
+        let mut index = DirIndex::build(&base, None);
+        let x = DirIdentity::of(&base.join("a/x")).unwrap();
+        index.remove_tree(&base.join("a"));
+        assert_eq!(indexed(&index, &base), ["b", "b/x"].map(PathBuf::from));
+        assert_eq!(index.find_identity(&x), None);
+        assert_eq!(
+            index.named(OsStr::new("x")).collect::<Vec<_>>(),
+            [base.join("b/x")]
+        );
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
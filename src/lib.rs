@@ -0,0 +1,43 @@
+//! Watches directory trees and reports what happens to the folders in them:
+//! creations, removals, renames and moves, including moves that were only
+//! recognised by their contents.
+//!
+//! Configure a [`DirMonitor`] with [`DirMonitor::builder`] and iterate over
+//! it to receive [`DirEvent`]s. The `dirmon` binary is a thin wrapper that
+//! writes these events to a log file.
+
+pub mod backend;
+mod error;
+mod event;
+pub mod filter;
+mod fingerprint;
+mod identity;
+mod index;
+pub mod lineage;
+mod manifest;
+mod monitor;
+mod rename;
+mod resolve;
+mod root;
+pub mod shadow;
+mod snapshot;
+mod state;
+pub mod trash;
+mod tree;
+
+pub use backend::Backend;
+pub use error::Error;
+pub use event::DirEvent;
+pub use filter::Filter;
+pub use manifest::Manifest;
+pub use monitor::{Depth, DirMonitor, DirMonitorBuilder, Root};
+
+/// Internals used by the benchmarks; not part of the public API.
+#[doc(hidden)]
+pub mod bench {
+    pub use crate::{
+        index::DirIndex,
+        resolve::{resolve_by_walk, resolve_move, MoveResolution},
+        snapshot::DirSnapshot,
+    };
+}
@@ -0,0 +1,180 @@
+//! Where each tracked directory has been.
+//!
+//! Every tracked directory gets an id the first time it is seen. The id is
+//! saved with the directory in the state file and follows it through moves
+//! and renames, and each change to the directory is recorded under it. The
+//! recorded steps tell where a directory that used to be at some path is
+//! now, or where it was last seen.
+
+use crate::{error::Error, event::DirEvent, state::State};
+use serde::{Deserialize, Serialize};
+use std::{
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// How long the lineage of a directory that is no longer tracked is kept
+/// after its last change.
+pub(crate) const RETENTION: Duration = Duration::from_secs(90 * 24 * 60 * 60);
+
+/// What happened to a directory in one step of its lineage.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Change {
+    /// It was already there when it was first tracked
+    FirstSeen,
+    Created,
+    Copied,
+    Moved,
+    Renamed,
+    MovedOut,
+    Trashed,
+    /// It vanished and could have gone to several places
+    AmbiguousMove,
+    Removed,
+}
+
+impl Change {
+    /// The change a directory went through in `event`, if any.
+    pub(crate) fn of(event: &DirEvent) -> Option<Change> {
+        match event {
+            DirEvent::Created { .. } => Some(Change::Created),
+            DirEvent::Copied { .. } => Some(Change::Copied),
+            DirEvent::Moved { .. } => Some(Change::Moved),
+            DirEvent::Renamed { .. } => Some(Change::Renamed),
+            DirEvent::MovedOut { .. } => Some(Change::MovedOut),
+            DirEvent::Trashed { .. } => Some(Change::Trashed),
+            DirEvent::AmbiguousMove { .. } => Some(Change::AmbiguousMove),
+            DirEvent::Removed { .. } => Some(Change::Removed),
+            DirEvent::Started { .. } | DirEvent::Error { .. } => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Change::FirstSeen => "first seen",
+            Change::Created => "created",
+            Change::Copied => "copied",
+            Change::Moved => "moved",
+            Change::Renamed => "renamed",
+            Change::MovedOut => "moved out",
+            Change::Trashed => "trashed",
+            Change::AmbiguousMove => "ambiguous move",
+            Change::Removed => "removed",
+        }
+    }
+}
+
+/// One change in the life of a directory.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Step {
+    /// When the change was seen; for changes made while no monitor was
+    /// running, when they were found at start
+    #[serde(with = "rfc3339")]
+    pub at: SystemTime,
+    pub change: Change,
+    /// Where the directory was after the change; for a removal, where it
+    /// was removed from
+    pub path: PathBuf,
+    /// The directory a copy was made from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub from: Option<PathBuf>,
+    /// The change was made to a directory above this one
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub with_parent: bool,
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub offline: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Step times are kept readable in the state file.
+mod rfc3339 {
+    use chrono::{DateTime, SecondsFormat, Utc};
+    use serde::{de::Error, Deserialize, Deserializer, Serializer};
+    use std::time::SystemTime;
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        let time: DateTime<Utc> = (*time).into();
+        serializer.serialize_str(&time.to_rfc3339_opts(SecondsFormat::Micros, true))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let text = String::deserialize(deserializer)?;
+        DateTime::parse_from_rfc3339(&text)
+            .map(SystemTime::from)
+            .map_err(D::Error::custom)
+    }
+}
+
+/// The recorded history of one directory.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Lineage {
+    pub id: String,
+    /// Name of the root the directory was tracked in
+    pub root: String,
+    /// Oldest first
+    pub steps: Vec<Step>,
+}
+
+impl Lineage {
+    /// Reads the lineages saved in `state_file` that match `query`: the
+    /// directory with that id, or else every directory that has been at
+    /// that path. They are ordered by when they were first seen.
+    ///
+    /// A path matches as saved, which starts with the root as configured,
+    /// or as an absolute path or one relative to the current directory.
+    pub fn find(state_file: &Path, query: &str) -> Result<Vec<Lineage>, Error> {
+        let state = State::load(state_file)?;
+        let wanted = std::path::absolute(query).ok();
+        let mut by_path = Vec::new();
+        for (root, root_state) in state.roots {
+            // Saved paths are only relative to the current directory in
+            // state from before the root's absolute path was saved
+            let absolute = |path: &Path| match (
+                &root_state.absolute_path,
+                path.strip_prefix(&root_state.path),
+            ) {
+                (Some(root), Ok(rest)) => Some(root.join(rest)),
+                _ => std::path::absolute(path).ok(),
+            };
+            for (id, steps) in root_state.lineage {
+                if id == query {
+                    return Ok(vec![Lineage { id, root, steps }]);
+                }
+                let been_there = steps.iter().any(|step| {
+                    step.path.as_os_str() == query
+                        || wanted.is_some() && absolute(&step.path) == wanted
+                });
+                if been_there {
+                    by_path.push(Lineage {
+                        id,
+                        root: root.clone(),
+                        steps,
+                    });
+                }
+            }
+        }
+        by_path.sort_by_key(|lineage| lineage.steps.first().map(|step| step.at));
+        Ok(by_path)
+    }
+}
+
+/// A new directory id: the current time in microseconds, in hex, moved past
+/// the last id handed out so two are never the same.
+pub(crate) fn new_id() -> String {
+    static LAST: AtomicU64 = AtomicU64::new(0);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_micros() as u64)
+        .unwrap_or_default();
+    let previous = LAST
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |last| {
+            Some(now.max(last + 1))
+        })
+        .unwrap_or_default();
+    format!("{:x}", now.max(previous + 1))
+}
@@ -0,0 +1,716 @@
+use crate::sink::{Entry, EventSink};
+use chrono::{DateTime, Local, NaiveDateTime, Utc};
+use dirmon::{DirEvent, Manifest};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+};
+
+/// Layout of the log file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// RFC 4180 CSV with a header row and fixed columns
+    #[default]
+    Csv,
+    /// One JSON object per line with typed fields
+    Jsonl,
+}
+
+/// What a log entry reports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    Started,
+    Created,
+    Copied,
+    Removed,
+    Moved,
+    Renamed,
+    MovedAndRenamed,
+    MovedOut,
+    Trashed,
+    AmbiguousMove,
+    Error,
+}
+
+impl RecordKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RecordKind::Started => "started",
+            RecordKind::Created => "created",
+            RecordKind::Copied => "copied",
+            RecordKind::Removed => "removed",
+            RecordKind::Moved => "moved",
+            RecordKind::Renamed => "renamed",
+            RecordKind::MovedAndRenamed => "moved_and_renamed",
+            RecordKind::MovedOut => "moved_out",
+            RecordKind::Trashed => "trashed",
+            RecordKind::AmbiguousMove => "ambiguous_move",
+            RecordKind::Error => "error",
+        }
+    }
+}
+
+/// One entry for the log.
+#[derive(Debug, Clone)]
+pub struct LogRecord {
+    pub kind: RecordKind,
+    /// The directory the entry is about, where it was before a move
+    pub path: Option<PathBuf>,
+    /// Where a moved or renamed directory is now
+    pub new_path: Option<PathBuf>,
+    /// How sure a move is, when it was not matched by identity
+    pub confidence: Option<f64>,
+    /// When a trashed directory was deleted, as recorded by the trash
+    pub deleted_at: Option<NaiveDateTime>,
+    pub detail: Option<String>,
+    /// What a removed or moved directory held when it was last seen
+    pub manifest: Option<Manifest>,
+    /// The change happened while dirmon was not running
+    pub offline: bool,
+    /// The entry as prose, kept for readers of the older log layout
+    pub message: String,
+}
+
+impl LogRecord {
+    fn new(kind: RecordKind, message: String) -> LogRecord {
+        LogRecord {
+            kind,
+            path: None,
+            new_path: None,
+            confidence: None,
+            deleted_at: None,
+            detail: None,
+            manifest: None,
+            offline: false,
+            message,
+        }
+    }
+
+    pub fn started() -> LogRecord {
+        LogRecord::new(RecordKind::Started, "Monitoring for changes".to_string())
+    }
+
+    /// `top_level` tells whether `path` is directly inside the root.
+    pub fn created(path: &Path, top_level: bool) -> LogRecord {
+        let what = if top_level {
+            "New top-level directory"
+        } else {
+            "New directory"
+        };
+        LogRecord {
+            path: Some(path.to_path_buf()),
+            ..LogRecord::new(RecordKind::Created, format!("{} created: {:?}", what, path))
+        }
+    }
+
+    /// A directory created under a placeholder name and then given its real
+    /// one; only the final name is reported as created.
+    pub fn created_as(placeholder: &Path, path: &Path, top_level: bool) -> LogRecord {
+        let record = LogRecord::created(path, top_level);
+        LogRecord {
+            detail: Some(format!("named from placeholder {:?}", placeholder)),
+            message: record.message.replace(" created: ", " created as: "),
+            ..record
+        }
+    }
+
+    /// A directory created at `to` as a copy of `from`.
+    pub fn copied(from: &Path, to: &Path, top_level: bool) -> LogRecord {
+        let record = LogRecord::created(to, top_level);
+        LogRecord {
+            kind: RecordKind::Copied,
+            path: Some(from.to_path_buf()),
+            new_path: Some(to.to_path_buf()),
+            message: format!("{} (copied from {:?})", record.message, from),
+            ..record
+        }
+    }
+
+    /// `snapshot` is the id the directory's contents were kept under.
+    pub fn removed(path: &Path, snapshot: Option<&str>) -> LogRecord {
+        let restore = snapshot.map(|id| format!("restore with: dirmon restore {}", id));
+        let mut message = format!("Directory removed: {:?}", path);
+        if let Some(restore) = &restore {
+            message.push_str(&format!(" ({})", restore));
+        }
+        LogRecord {
+            path: Some(path.to_path_buf()),
+            detail: restore,
+            ..LogRecord::new(RecordKind::Removed, message)
+        }
+    }
+
+    /// Describes how a directory got from `from` to `to`.
+    pub fn moved(from: &Path, to: &Path) -> LogRecord {
+        let dir_name = from.file_name().unwrap_or_default().to_string_lossy();
+        let same_name = from.file_name() == to.file_name();
+        let same_parent = from.parent() == to.parent();
+        let (kind, verb) = match (same_parent, same_name) {
+            (true, _) => (RecordKind::Renamed, "renamed"),
+            (false, true) => (RecordKind::Moved, "moved"),
+            (false, false) => (RecordKind::MovedAndRenamed, "moved and renamed"),
+        };
+        LogRecord {
+            path: Some(from.to_path_buf()),
+            new_path: Some(to.to_path_buf()),
+            ..LogRecord::new(
+                kind,
+                format!("Directory '{}' {} to: {:?}", dir_name, verb, to),
+            )
+        }
+    }
+
+    /// A move matched on content rather than identity.
+    pub fn likely_moved(from: &Path, to: &Path, confidence: f64) -> LogRecord {
+        let record = LogRecord::moved(from, to);
+        LogRecord {
+            confidence: Some(confidence),
+            message: format!("{} (confidence {:.2})", record.message, confidence),
+            ..record
+        }
+    }
+
+    /// A directory that left the root for `to`, outside it. `confidence` is
+    /// set when it was matched on content.
+    pub fn moved_out(from: &Path, to: &Path, confidence: Option<f64>) -> LogRecord {
+        let dir_name = from.file_name().unwrap_or_default().to_string_lossy();
+        let mut message = format!("Directory '{}' moved out to: {:?}", dir_name, to);
+        if let Some(confidence) = confidence {
+            message.push_str(&format!(" (confidence {:.2})", confidence));
+        }
+        LogRecord {
+            path: Some(from.to_path_buf()),
+            new_path: Some(to.to_path_buf()),
+            confidence,
+            ..LogRecord::new(RecordKind::MovedOut, message)
+        }
+    }
+
+    /// A directory deleted to a trash, where it now is at `trashed_to`.
+    pub fn trashed(path: &Path, trashed_to: &Path, deleted_at: Option<NaiveDateTime>) -> LogRecord {
+        let restore = format!(
+            "restore with: dirmon untrash {}",
+            shell_quote(&trashed_to.display().to_string())
+        );
+        LogRecord {
+            path: Some(path.to_path_buf()),
+            new_path: Some(trashed_to.to_path_buf()),
+            deleted_at,
+            detail: Some(restore.clone()),
+            ..LogRecord::new(
+                RecordKind::Trashed,
+                format!("Directory trashed: {:?} ({})", path, restore),
+            )
+        }
+    }
+
+    /// A move that could have gone to any of `candidates`, best first.
+    pub fn ambiguous_move(from: &Path, candidates: &[(PathBuf, f64)]) -> LogRecord {
+        let dir_name = from.file_name().unwrap_or_default().to_string_lossy();
+        let listed: Vec<String> = candidates
+            .iter()
+            .map(|(path, confidence)| format!("{:?} ({:.2})", path, confidence))
+            .collect();
+        let listed = listed.join(" or ");
+        LogRecord {
+            path: Some(from.to_path_buf()),
+            detail: Some(listed.clone()),
+            ..LogRecord::new(
+                RecordKind::AmbiguousMove,
+                format!(
+                    "Directory '{}' move is ambiguous between: {}",
+                    dir_name, listed
+                ),
+            )
+        }
+    }
+
+    pub fn error(message: &str) -> LogRecord {
+        LogRecord {
+            detail: Some(message.to_string()),
+            ..LogRecord::new(RecordKind::Error, format!("Error: {}", message))
+        }
+    }
+
+    /// Everything a CSV row has no column for: the offline marker, the
+    /// confidence of a move and the detail text.
+    fn csv_detail(&self) -> String {
+        let mut parts = Vec::new();
+        if self.offline {
+            parts.push("changed while offline".to_string());
+        }
+        if let Some(confidence) = self.confidence {
+            parts.push(format!("confidence {:.2}", confidence));
+        }
+        if let Some(deleted_at) = self.deleted_at {
+            parts.push(format!(
+                "deleted {}",
+                deleted_at.format("%Y-%m-%d %H:%M:%S")
+            ));
+        }
+        parts.extend(self.detail.clone());
+        if let Some(manifest) = &self.manifest {
+            parts.push(describe_manifest(manifest));
+        }
+        parts.join("; ")
+    }
+
+    /// Adds a summary of what the directory held.
+    pub fn with_manifest(self, manifest: Option<&Manifest>) -> LogRecord {
+        let Some(manifest) = manifest else {
+            return self;
+        };
+        LogRecord {
+            message: format!("{} (held {})", self.message, totals(manifest)),
+            manifest: Some(manifest.clone()),
+            ..self
+        }
+    }
+
+    /// Marks the record as a change found when reconciling saved state.
+    pub fn offline(self) -> LogRecord {
+        LogRecord {
+            offline: true,
+            message: format!("Changed while offline: {}", self.message),
+            ..self
+        }
+    }
+}
+
+impl LogRecord {
+    /// Describes `event`, which happened under the root at `root_path`.
+    pub fn for_event(event: &DirEvent, root_path: &Path) -> LogRecord {
+        let record = match event {
+            DirEvent::Started { .. } => LogRecord::started(),
+            DirEvent::Created {
+                path,
+                placeholder: Some(placeholder),
+                ..
+            } => LogRecord::created_as(placeholder, path, path.parent() == Some(root_path)),
+            DirEvent::Created { path, .. } => {
+                LogRecord::created(path, path.parent() == Some(root_path))
+            }
+            DirEvent::Copied { from, to, .. } => {
+                LogRecord::copied(from, to, to.parent() == Some(root_path))
+            }
+            DirEvent::Removed { path, snapshot, .. } => {
+                LogRecord::removed(path, snapshot.as_deref())
+            }
+            DirEvent::Moved {
+                from,
+                to,
+                confidence,
+                ..
+            }
+            | DirEvent::Renamed {
+                from,
+                to,
+                confidence,
+                ..
+            } => match confidence {
+                Some(confidence) => LogRecord::likely_moved(from, to, *confidence),
+                None => LogRecord::moved(from, to),
+            },
+            DirEvent::MovedOut {
+                from,
+                to,
+                confidence,
+                ..
+            } => LogRecord::moved_out(from, to, *confidence),
+            DirEvent::Trashed {
+                path,
+                trashed_to,
+                deleted_at,
+                ..
+            } => LogRecord::trashed(path, trashed_to, *deleted_at),
+            DirEvent::AmbiguousMove {
+                from, candidates, ..
+            } => LogRecord::ambiguous_move(from, candidates),
+            DirEvent::Error { message, .. } => LogRecord::error(message),
+        };
+        let record = record.with_manifest(event.manifest());
+        if event.is_offline() {
+            record.offline()
+        } else {
+            record
+        }
+    }
+}
+
+/// Field layout of a JSON Lines entry, also posted by webhooks.
+#[derive(Serialize)]
+pub struct JsonRecord<'a> {
+    event: RecordKind,
+    path: Option<&'a Path>,
+    new_path: Option<&'a Path>,
+    root: &'a str,
+    detected_at: String,
+    dirmon_version: &'static str,
+    offline: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    confidence: Option<f64>,
+    /// Local time without an offset, as trashes record it
+    #[serde(skip_serializing_if = "Option::is_none")]
+    deleted_at: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detail: Option<&'a str>,
+    /// What a removed or moved directory held
+    #[serde(skip_serializing_if = "Option::is_none")]
+    contents: Option<JsonManifest<'a>>,
+    message: &'a str,
+}
+
+#[derive(Serialize)]
+struct JsonManifest<'a> {
+    files: u64,
+    bytes: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    newest: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    largest: Vec<JsonEntry<'a>>,
+}
+
+#[derive(Serialize)]
+struct JsonEntry<'a> {
+    name: &'a str,
+    bytes: u64,
+}
+
+impl<'a> JsonManifest<'a> {
+    fn new(manifest: &'a Manifest) -> JsonManifest<'a> {
+        JsonManifest {
+            files: manifest.files,
+            bytes: manifest.bytes,
+            newest: manifest
+                .newest
+                .map(|t| DateTime::<Utc>::from(t).to_rfc3339()),
+            largest: manifest
+                .largest
+                .iter()
+                .map(|(name, bytes)| JsonEntry {
+                    name,
+                    bytes: *bytes,
+                })
+                .collect(),
+        }
+    }
+}
+
+impl<'a> JsonRecord<'a> {
+    pub fn new(entry: &'a Entry) -> JsonRecord<'a> {
+        let record = &entry.record;
+        JsonRecord {
+            event: record.kind,
+            path: record.path.as_deref(),
+            new_path: record.new_path.as_deref(),
+            root: &entry.root,
+            detected_at: entry.time.to_rfc3339(),
+            dirmon_version: env!("CARGO_PKG_VERSION"),
+            offline: record.offline,
+            confidence: record.confidence,
+            deleted_at: record
+                .deleted_at
+                .map(|d| d.format("%Y-%m-%dT%H:%M:%S").to_string()),
+            detail: record.detail.as_deref(),
+            contents: record.manifest.as_ref().map(JsonManifest::new),
+            message: &record.message,
+        }
+    }
+}
+
+/// Column names of CSV logs; the message column is optional.
+const CSV_HEADER: &[&str] = &["timestamp", "event", "path", "new_path", "root", "detail"];
+
+/// Appends entries to a log file in one of the [`LogFormat`]s.
+///
+/// The file is reopened for every entry, so it may be rotated or removed
+/// while dirmon runs. A CSV log that does not start with the header this
+/// sink writes, such as one in the older layout, is moved aside rather than
+/// appended to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileSink {
+    pub path: PathBuf,
+    pub format: LogFormat,
+    /// Whether CSV logs keep the free-text message as a last column
+    pub csv_message: bool,
+}
+
+impl FileSink {
+    /// The header row of CSV logs, as written.
+    fn csv_header(&self) -> io::Result<Vec<u8>> {
+        let mut csv = csv::WriterBuilder::new()
+            .has_headers(false)
+            .from_writer(Vec::new());
+        let message_column = self.csv_message.then_some("message");
+        csv.write_record(CSV_HEADER.iter().copied().chain(message_column))?;
+        csv.into_inner().map_err(|e| e.into_error())
+    }
+
+    /// Makes sure rows are only appended under `header`: a log that starts
+    /// with anything else is renamed with the time it was moved aside, e.g.
+    /// `dirmon_log.20240301-101500.csv`. Returns whether the file needs the
+    /// header written.
+    fn start_csv(&self, header: &[u8]) -> io::Result<bool> {
+        let mut start = Vec::with_capacity(header.len());
+        match File::open(&self.path) {
+            Ok(file) => file.take(header.len() as u64).read_to_end(&mut start)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(e) => return Err(e),
+        };
+        if start.is_empty() {
+            return Ok(true);
+        }
+        if start == header {
+            return Ok(false);
+        }
+
+        let stamp = Local::now().format("%Y%m%d-%H%M%S");
+        let stem = self.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match self.path.extension() {
+            Some(ext) => format!("{}.{}.{}", stem, stamp, ext.to_string_lossy()),
+            None => format!("{}.{}", stem, stamp),
+        };
+        let aside = self.path.with_file_name(name);
+        if aside.exists() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!(
+                    "{} has another layout and {} is taken",
+                    self.path.display(),
+                    aside.display()
+                ),
+            ));
+        }
+        std::fs::rename(&self.path, &aside)?;
+        eprintln!(
+            "dirmon: {} has another layout; moved it to {}",
+            self.path.display(),
+            aside.display()
+        );
+        Ok(true)
+    }
+}
+
+impl EventSink for FileSink {
+    fn describe(&self) -> String {
+        format!("log {}", self.path.display())
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        // The header to start a new CSV log with
+        let header = match self.format {
+            LogFormat::Csv => {
+                let header = self.csv_header()?;
+                self.start_csv(&header)?.then_some(header)
+            }
+            LogFormat::Jsonl => None,
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        let mut writer = BufWriter::new(file);
+        let record = &entry.record;
+
+        match self.format {
+            LogFormat::Csv => {
+                if let Some(header) = &header {
+                    writer.write_all(header)?;
+                }
+                let mut csv = csv::WriterBuilder::new()
+                    .has_headers(false)
+                    .from_writer(writer);
+                let path = display_path(record.path.as_deref());
+                let new_path = display_path(record.new_path.as_deref());
+                let detail = record.csv_detail();
+                let mut row = vec![
+                    entry.timestamp.as_str(),
+                    record.kind.as_str(),
+                    &path,
+                    &new_path,
+                    &entry.root,
+                    &detail,
+                ];
+                if self.csv_message {
+                    row.push(&record.message);
+                }
+                csv.write_record(row)?;
+                csv.flush()?;
+            }
+            LogFormat::Jsonl => {
+                serde_json::to_writer(&mut writer, &JsonRecord::new(entry))?;
+                writer.write_all(b"\n")?;
+                writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// File count and total size, e.g. "12 files, 3.4 MB".
+fn totals(manifest: &Manifest) -> String {
+    let files = if manifest.files == 1 { "file" } else { "files" };
+    format!(
+        "{} {}, {}",
+        manifest.files,
+        files,
+        human_size(manifest.bytes)
+    )
+}
+
+/// Everything in `manifest`, for the CSV detail column.
+fn describe_manifest(manifest: &Manifest) -> String {
+    let mut text = format!("held {}", totals(manifest));
+    if let Some(newest) = manifest.newest {
+        let newest = DateTime::<Utc>::from(newest);
+        text.push_str(&format!(
+            ", newest {}",
+            newest.format("%Y-%m-%d %H:%M:%S UTC")
+        ));
+    }
+    if !manifest.largest.is_empty() {
+        let largest: Vec<String> = manifest
+            .largest
+            .iter()
+            .map(|(name, bytes)| format!("{} ({})", name, human_size(*bytes)))
+            .collect();
+        text.push_str(&format!(", largest {}", largest.join(", ")));
+    }
+    text
+}
+
+/// `bytes` in decimal units with one decimal place above a kilobyte.
+fn human_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["bytes", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// `s` quoted for a POSIX shell, so a logged command can be pasted as is.
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+fn display_path(path: Option<&Path>) -> String {
+    path.map(|p| p.display().to_string()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+
+    /// A CSV log in a directory of its own, removed first if a previous run
+    /// left it behind.
+    fn csv_sink(name: &str) -> FileSink {
+        let dir = std::env::temp_dir().join(format!("dirmon-log-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        FileSink {
+            path: dir.join("log.csv"),
+            format: LogFormat::Csv,
+            csv_message: true,
+        }
+    }
+
+    fn remove(sink: &FileSink) {
+        let _ = std::fs::remove_dir_all(sink.path.parent().unwrap());
+    }
+
+    fn entry(record: LogRecord) -> Entry {
+        let time = DateTime::parse_from_rfc3339("2024-03-01T10:15:00-05:00").unwrap();
+        Entry {
+            root: "root".to_string(),
+            time,
+            timestamp: time.to_rfc3339(),
+            record,
+        }
+    }
+
+    fn rows(path: &Path) -> Vec<csv::StringRecord> {
+        csv::Reader::from_path(path)
+            .unwrap()
+            .records()
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn quotes_commas_quotes_and_newlines_in_paths() {
+        let mut sink = csv_sink("quoting");
+        let from = Path::new("/r/Acme, \"Inc\"\nold");
+        let to = Path::new("/r/Acme Inc");
+        sink.write(&entry(LogRecord::moved(from, to))).unwrap();
+
+        let text = std::fs::read_to_string(&sink.path).unwrap();
+        assert!(text.contains(",\"/r/Acme, \"\"Inc\"\"\nold\",/r/Acme Inc,"));
+        let rows = rows(&sink.path);
+        assert_eq!(rows.len(), 1);
+        assert_eq!(&rows[0][1], "renamed");
+        assert_eq!(&rows[0][2], "/r/Acme, \"Inc\"\nold");
+        assert_eq!(&rows[0][3], "/r/Acme Inc");
+        assert_eq!(rows[0].len(), CSV_HEADER.len() + 1);
+        remove(&sink);
+    }
+
+    #[test]
+    fn writes_the_header_once() {
+        let mut sink = csv_sink("header");
+        sink.write(&entry(LogRecord::started())).unwrap();
+        sink.write(&entry(LogRecord::created(Path::new("/r/a"), true)))
+            .unwrap();
+
+        let text = std::fs::read_to_string(&sink.path).unwrap();
+        assert!(text.starts_with("timestamp,event,path,new_path,root,detail,message"));
+        assert_eq!(text.matches("timestamp,event").count(), 1);
+        assert_eq!(rows(&sink.path).len(), 2);
+        remove(&sink);
+    }
+
+    #[test]
+    fn moves_aside_a_log_with_another_layout() {
+        let mut sink = csv_sink("layout");
+        let old = "2024-03-01 10:00:00,Monitoring for changes\n";
+        std::fs::write(&sink.path, old).unwrap();
+        sink.write(&entry(LogRecord::started())).unwrap();
+
+        let text = std::fs::read_to_string(&sink.path).unwrap();
+        assert!(text.starts_with("timestamp,event,"));
+        assert_eq!(rows(&sink.path).len(), 1);
+        let aside: Vec<PathBuf> = std::fs::read_dir(sink.path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| *path != sink.path)
+            .collect();
+        assert_eq!(aside.len(), 1);
+        assert_eq!(std::fs::read_to_string(&aside[0]).unwrap(), old);
+        remove(&sink);
+    }
+
+    #[test]
+    fn moves_aside_a_log_written_without_the_message_column() {
+        let mut sink = csv_sink("columns");
+        sink.csv_message = false;
+        sink.write(&entry(LogRecord::started())).unwrap();
+        sink.csv_message = true;
+        sink.write(&entry(LogRecord::started())).unwrap();
+
+        let text = std::fs::read_to_string(&sink.path).unwrap();
+        assert!(text.starts_with("timestamp,event,path,new_path,root,detail,message"));
+        assert_eq!(rows(&sink.path).len(), 1);
+        remove(&sink);
+    }
+}
@@ -1,123 +1,168 @@
-use chrono::{FixedOffset, Local};
-use notify::{Config, EventKind, PollWatcher, RecursiveMode, Watcher};
+mod cli;
+mod clock;
+mod config;
+mod log;
+mod sink;
+mod syslog;
+mod webhook;
+
+use clap::Parser;
+use cli::{Cli, Command, HistoryArgs, RestoreArgs, UntrashArgs, WatchArgs};
+use config::Settings;
+use dirmon::{lineage::Lineage, shadow::Snapshot, trash::TrashInfo, DirMonitor};
+use log::LogRecord;
+use signal_hook::consts::{SIGINT, SIGTERM};
+use sink::{Entry, FanOut};
 use std::{
-    collections::HashSet,
-    fs::OpenOptions,
-    io::{BufWriter, Write},
     path::Path,
-    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
     time::Duration,
 };
-use walkdir::WalkDir;
-
-fn find_moved_directory(dir_name: &str, search_path: &Path) -> Option<PathBuf> {
-    WalkDir::new(search_path)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .find(|e| e.file_type().is_dir() && e.file_name().to_string_lossy() == dir_name)
-        .map(|e| e.path().to_path_buf())
-}
 
-fn write_to_log(message: &str, offset: &FixedOffset) -> std::io::Result<()> {
-    let est_time = Local::now().with_timezone(offset);
-    let log_entry = format!("{},{}\n", message, est_time.format("%Y-%m-%d %H:%M:%S %z"));
-    let file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open("dirmon_log.csv")?;
-    let mut writer = BufWriter::new(file);
+/// How often the watch loop checks whether it was asked to stop.
+const STOP_CHECK: Duration = Duration::from_millis(500);
+
+fn watch(args: &WatchArgs) -> Result<(), String> {
+    let settings = Settings::from_args(args)?;
+
+    let mut sinks = FanOut::new(settings.fallback_file.clone());
+    for sink in &settings.sinks {
+        sinks.add(sink.kind.open()?, sink.roots.clone());
+    }
+
+    let mut builder = DirMonitor::builder().state_file(&settings.state_file);
+    for root in settings.roots {
+        builder = builder.root(root);
+    }
+    let mut monitor = builder.build().map_err(|e| e.to_string())?;
+
+    // Stop cleanly on the first SIGINT or SIGTERM, so buffered entries are
+    // written or spilled to the fallback file; a second one exits at once
+    let stop = Arc::new(AtomicBool::new(false));
+    for signal in [SIGINT, SIGTERM] {
+        signal_hook::flag::register_conditional_shutdown(signal, 1, Arc::clone(&stop))
+            .and_then(|_| signal_hook::flag::register(signal, Arc::clone(&stop)))
+            .map_err(|e| format!("cannot handle signals: {}", e))?;
+    }
+
+    while !stop.load(Ordering::Relaxed) {
+        let Some(event) = monitor.next_timeout(STOP_CHECK) else {
+            continue;
+        };
+        let root_path = monitor.root_path(event.root()).unwrap_or(Path::new(""));
+        let (time, timestamp) = settings.clock.now();
+        sinks.send(Entry {
+            root: event.root().to_string(),
+            time,
+            timestamp,
+            record: LogRecord::for_event(&event, root_path),
+        });
+    }
+
+    Ok(())
+}
 
-    writer.write_all(log_entry.as_bytes())?;
+fn untrash(args: &UntrashArgs) -> Result<(), String> {
+    let info = TrashInfo::find(&args.path).map_err(|e| e.to_string())?;
+    info.restore().map_err(|e| e.to_string())?;
+    println!(
+        "Restored {} to {}",
+        info.trashed.display(),
+        info.original.display()
+    );
     Ok(())
 }
 
-fn main() {
-    let est_offset = FixedOffset::west_opt(5 * 3600).unwrap();
-    let (tx, rx) = std::sync::mpsc::channel();
-
-    // Initialize directory cache for top-level folders
-    let mut known_directories: HashSet<PathBuf> = HashSet::new();
-
-    // Scan initial top-level directories
-    let watch_path = Path::new("./");
-    for entry in std::fs::read_dir(watch_path).unwrap() {
-        if let Ok(entry) = entry {
-            if entry.path().is_dir() {
-                //let message = format!("Initially found directory: {:?}", entry.path());
-                //write_to_log(&message, &est_offset).unwrap();
-                known_directories.insert(entry.path());
+fn restore(args: &RestoreArgs) -> Result<(), String> {
+    let dirs = match (&args.shadow_dir, &args.config) {
+        (Some(dir), _) => vec![dir.clone()],
+        (None, Some(config)) => config::shadow_dirs(config)?,
+        (None, None) => return Err("no shadow directory given".to_string()),
+    };
+
+    let Some(id) = &args.id else {
+        for dir in &dirs {
+            for snapshot in Snapshot::list(dir).map_err(|e| e.to_string())? {
+                println!(
+                    "{}  {}  [{}] {}",
+                    snapshot.id,
+                    snapshot.removed_at.to_rfc3339(),
+                    snapshot.root,
+                    snapshot.original.display()
+                );
             }
         }
+        return Ok(());
+    };
+
+    let snapshot = dirs
+        .iter()
+        .find_map(|dir| Snapshot::find(dir, id).ok())
+        .ok_or_else(|| format!("no snapshot {}", id))?;
+    let to = args.to.as_ref().unwrap_or(&snapshot.original);
+    snapshot.restore(to).map_err(|e| e.to_string())?;
+    println!("Restored {} from snapshot {}", to.display(), id);
+    Ok(())
+}
+
+fn history(args: &HistoryArgs) -> Result<(), String> {
+    let (state_file, clock) = config::history_settings(args)?;
+    let lineages = Lineage::find(&state_file, &args.query).map_err(|e| e.to_string())?;
+    if lineages.is_empty() {
+        return Err(format!(
+            "no folder with id or path {} in {}",
+            args.query,
+            state_file.display()
+        ));
     }
 
-    let config = Config::default().with_poll_interval(Duration::from_secs(60));
-    let mut watcher = PollWatcher::new(tx, config).unwrap();
-
-    watcher.watch(watch_path, RecursiveMode::Recursive).unwrap();
-
-    let message = format!("Monitoring for changes");
-    write_to_log(&message, &est_offset).unwrap();
-
-    for e in rx {
-        match e {
-            Ok(event) => {
-                match event.kind {
-                    EventKind::Create(_) => {
-                        for path in &event.paths {
-                            // Check if it's a directory and is at top level
-                            if path.is_dir() && path.parent() == Some(watch_path) {
-                                //squelch log entries regarding New folder
-                                if path != Path::new("./New folder") {
-                                    let message =
-                                        format!("New top-level directory created: {:?}", path);
-                                    write_to_log(&message, &est_offset).unwrap();
-                                }
-                                known_directories.insert(path.to_path_buf());
-                            }
-                        }
-                    }
-                    EventKind::Remove(_) => {
-                        for path in &event.paths {
-                            if known_directories.contains(path) {
-                                let dir_name = path
-                                    .file_name()
-                                    .unwrap_or_default()
-                                    .to_string_lossy()
-                                    .to_string();
-
-                                // Search recursively for the moved directory
-                                if let Some(new_path) =
-                                    find_moved_directory(&dir_name, Path::new("./"))
-                                {
-                                    let message = format!(
-                                        "Directory '{}' moved to: {:?}",
-                                        dir_name, new_path
-                                    );
-                                    write_to_log(&message, &est_offset).unwrap();
-                                    known_directories.remove(path);
-                                    // Only add to known directories if it's at top level
-                                    if new_path.parent() == Some(watch_path) {
-                                        known_directories.insert(new_path);
-                                    }
-                                } else {
-                                    //squelch log entries regarding New folder
-                                    if path != Path::new("./New folder") {
-                                        let message = format!("Directory removed: {:?}", path);
-                                        write_to_log(&message, &est_offset).unwrap();
-                                    }
-                                    known_directories.remove(path);
-                                }
-                            }
-                        }
-                    }
-                    _ => {}
-                }
+    for (n, lineage) in lineages.iter().enumerate() {
+        if n > 0 {
+            println!();
+        }
+        println!("{}  [{}]", lineage.id, lineage.root);
+        for step in &lineage.steps {
+            let (_, timestamp) = clock.at(step.at.into());
+            let mut line = format!(
+                "  {}  {:<14}  {}",
+                timestamp,
+                step.change.as_str(),
+                step.path.display()
+            );
+            if let Some(from) = &step.from {
+                line.push_str(&format!(" (from {})", from.display()));
+            }
+            if step.with_parent {
+                line.push_str(" (with its parent)");
             }
-            Err(error) => {
-                let message = format!("Error: {:?}", error);
-                write_to_log(&message, &est_offset).unwrap();
+            if step.offline {
+                line.push_str(" (while dirmon was not running)");
             }
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Some(Command::Watch(args)) => watch(&args),
+        Some(Command::Untrash(args)) => untrash(&args),
+        Some(Command::Restore(args)) => restore(&args),
+        Some(Command::History(args)) => history(&args),
+        None => watch(&cli.watch),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("dirmon: {}", e);
+            ExitCode::FAILURE
         }
     }
 }
@@ -0,0 +1,132 @@
+use crate::snapshot::DirSnapshot;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    path::{Component, Path, PathBuf},
+    time::SystemTime,
+};
+use walkdir::WalkDir;
+
+/// A summary of everything inside a directory, kept so that a log entry
+/// can say what went with it when it is removed or moved.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// Files anywhere below the directory
+    pub files: u64,
+    /// Their combined size in bytes
+    pub bytes: u64,
+    /// Modification time of the most recently changed file
+    pub newest: Option<SystemTime>,
+    /// The largest immediate children by the size of everything in them,
+    /// largest first; only as many as were asked for
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub largest: Vec<(String, u64)>,
+}
+
+/// The files below a directory that is not tracked on its own.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Totals {
+    pub files: u64,
+    pub bytes: u64,
+    pub newest: Option<SystemTime>,
+}
+
+impl Totals {
+    /// Counts the files anywhere below `path`. Paths for which `skip` holds
+    /// are left out, along with everything below them.
+    pub fn take(path: &Path, skip: impl Fn(&Path) -> bool) -> Totals {
+        let mut totals = Totals::default();
+        let mut walk = WalkDir::new(path).min_depth(1).into_iter();
+        while let Some(entry) = walk.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if skip(entry.path()) {
+                if entry.file_type().is_dir() {
+                    walk.skip_current_dir();
+                }
+                continue;
+            }
+            if entry.file_type().is_file() {
+                if let Ok(meta) = entry.metadata() {
+                    totals.add_file(meta.len(), meta.modified().ok());
+                }
+            }
+        }
+        totals
+    }
+
+    fn add_file(&mut self, size: u64, modified: Option<SystemTime>) {
+        self.files += 1;
+        self.bytes += size;
+        self.newest = self.newest.max(modified);
+    }
+
+    fn add(&mut self, other: &Totals) {
+        self.files += other.files;
+        self.bytes += other.bytes;
+        self.newest = self.newest.max(other.newest);
+    }
+}
+
+impl Manifest {
+    /// Adds up the manifest of the directory at the top of `dirs`, which are
+    /// it and the tracked directories below it, parents first, listing its
+    /// `entries` largest children.
+    ///
+    /// Each directory contributes the files directly inside it, from its
+    /// fingerprint, and the totals of its subdirectories that are not
+    /// tracked. Files for which `skip` holds are left out.
+    pub fn add_up(
+        dirs: &[(PathBuf, DirSnapshot)],
+        entries: usize,
+        skip: impl Fn(&Path) -> bool,
+    ) -> Option<Manifest> {
+        let (top, _) = dirs.first()?;
+        let mut totals = Totals::default();
+        let mut children: HashMap<String, u64> = HashMap::new();
+
+        for (path, snapshot) in dirs {
+            // The child of the top directory this one's contents count
+            // towards, unless it is the top directory itself
+            let under = path.strip_prefix(top).ok().and_then(first_name);
+            if let Some(under) = &under {
+                children.entry(under.clone()).or_default();
+            }
+            for (name, child) in snapshot.fingerprint.iter() {
+                let Some(size) = child.size else {
+                    continue;
+                };
+                if skip(&path.join(name)) {
+                    continue;
+                }
+                totals.add_file(size, child.modified());
+                let child = under.as_ref().unwrap_or(name);
+                *children.entry(child.clone()).or_default() += size;
+            }
+            for (name, subdir) in &snapshot.subdirs {
+                totals.add(subdir);
+                let child = under.as_ref().unwrap_or(name);
+                *children.entry(child.clone()).or_default() += subdir.bytes;
+            }
+        }
+
+        let mut largest: Vec<(String, u64)> = children.into_iter().collect();
+        largest.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        largest.truncate(entries);
+        Some(Manifest {
+            files: totals.files,
+            bytes: totals.bytes,
+            newest: totals.newest,
+            largest,
+        })
+    }
+}
+
+/// The first name in `path`, if it starts with one.
+fn first_name(path: &Path) -> Option<String> {
+    match path.components().next() {
+        Some(Component::Normal(name)) => Some(name.to_string_lossy().into_owned()),
+        _ => None,
+    }
+}
@@ -0,0 +1,581 @@
+use crate::{
+    backend::Backend,
+    error::Error,
+    event::DirEvent,
+    filter::Filter,
+    root::{RootMonitor, TaggedEvent},
+    shadow,
+    state::State,
+};
+use std::{
+    collections::{HashSet, VecDeque},
+    fmt,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    time::{Duration, Instant},
+};
+
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How long a removal waits for the directory to turn up under another name,
+/// long enough for both halves of a rename to arrive however they are
+/// reported.
+const DEFAULT_RENAME_WINDOW: Duration = Duration::from_secs(2);
+
+/// How often pending work, such as unpaired renames, is looked at when no
+/// events arrive.
+const TICK: Duration = Duration::from_millis(500);
+
+/// Minimum time between two saves of the state file.
+const STATE_SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Longest wait between attempts to save a state file that keeps failing;
+/// the wait doubles up to this from [`STATE_SAVE_INTERVAL`].
+const STATE_SAVE_MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+/// How far below a root directories are tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Depth {
+    /// Directories up to this many levels down; 1 is the top level only
+    Levels(usize),
+    Unlimited,
+}
+
+impl Default for Depth {
+    fn default() -> Depth {
+        Depth::Levels(1)
+    }
+}
+
+impl Depth {
+    /// Whether directories `level` levels below the root are tracked.
+    pub fn includes(self, level: usize) -> bool {
+        match self {
+            Depth::Levels(levels) => level <= levels,
+            Depth::Unlimited => true,
+        }
+    }
+
+    /// The deepest tracked level, if there is one.
+    pub fn max_level(self) -> Option<usize> {
+        match self {
+            Depth::Levels(levels) => Some(levels),
+            Depth::Unlimited => None,
+        }
+    }
+}
+
+impl fmt::Display for Depth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Depth::Levels(levels) => write!(f, "{}", levels),
+            Depth::Unlimited => f.write_str("unlimited"),
+        }
+    }
+}
+
+impl FromStr for Depth {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Depth, String> {
+        match s {
+            "unlimited" => Ok(Depth::Unlimited),
+            _ => match s.parse() {
+                Ok(levels) if levels > 0 => Ok(Depth::Levels(levels)),
+                _ => Err(format!(
+                    "invalid depth '{}', expected a number of levels or unlimited",
+                    s
+                )),
+            },
+        }
+    }
+}
+
+/// A directory to monitor. Settings left unset are taken from the
+/// [`DirMonitorBuilder`].
+#[derive(Debug, Clone)]
+pub struct Root {
+    path: PathBuf,
+    name: Option<String>,
+    backend: Option<Backend>,
+    poll_interval: Option<Duration>,
+    depth: Option<Depth>,
+    ignore: Option<Filter>,
+    placeholders: Option<Filter>,
+    search_path: Option<PathBuf>,
+    search_roots: Option<Vec<PathBuf>>,
+    detect_trash: Option<bool>,
+    shadow: Option<PathBuf>,
+    shadow_retention: Option<Duration>,
+    manifest_entries: Option<usize>,
+    rename_window: Option<Duration>,
+}
+
+impl Root {
+    pub fn new(path: impl Into<PathBuf>) -> Root {
+        Root {
+            path: path.into(),
+            name: None,
+            backend: None,
+            poll_interval: None,
+            depth: None,
+            ignore: None,
+            placeholders: None,
+            search_path: None,
+            search_roots: None,
+            detect_trash: None,
+            shadow: None,
+            shadow_retention: None,
+            manifest_entries: None,
+            rename_window: None,
+        }
+    }
+
+    /// Label carried by every event from this root; defaults to its path.
+    pub fn name(mut self, name: impl Into<String>) -> Root {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn backend(mut self, backend: Backend) -> Root {
+        self.backend = Some(backend);
+        self
+    }
+
+    pub fn poll_interval(mut self, interval: Duration) -> Root {
+        self.poll_interval = Some(interval);
+        self
+    }
+
+    pub fn depth(mut self, depth: Depth) -> Root {
+        self.depth = Some(depth);
+        self
+    }
+
+    /// Directories that are neither tracked nor reported.
+    pub fn ignore(mut self, filter: Filter) -> Root {
+        self.ignore = Some(filter);
+        self
+    }
+
+    /// Default folder names that are reported once the folder is renamed.
+    pub fn placeholders(mut self, filter: Filter) -> Root {
+        self.placeholders = Some(filter);
+        self
+    }
+
+    /// Directory searched for a vanished folder's new location; defaults to
+    /// the root itself.
+    pub fn search_path(mut self, path: impl Into<PathBuf>) -> Root {
+        self.search_path = Some(path.into());
+        self
+    }
+
+    /// Directories outside the root, such as other shares or archive
+    /// folders, searched for a vanished folder that is not found inside it.
+    pub fn search_roots(mut self, paths: Vec<PathBuf>) -> Root {
+        self.search_roots = Some(paths);
+        self
+    }
+
+    /// Whether folders deleted to a freedesktop.org Trash are reported as
+    /// trashed rather than removed.
+    pub fn detect_trash(mut self, detect: bool) -> Root {
+        self.detect_trash = Some(detect);
+        self
+    }
+
+    /// Keeps a hardlinked mirror of the root in `dir`, which must be on the
+    /// same filesystem, so removed directories can be restored.
+    pub fn shadow(mut self, dir: impl Into<PathBuf>) -> Root {
+        self.shadow = Some(dir.into());
+        self
+    }
+
+    /// How long removed directories are kept in the shadow directory.
+    pub fn shadow_retention(mut self, retention: Duration) -> Root {
+        self.shadow_retention = Some(retention);
+        self
+    }
+
+    /// How many of a directory's largest entries its manifest lists.
+    pub fn manifest_entries(mut self, entries: usize) -> Root {
+        self.manifest_entries = Some(entries);
+        self
+    }
+
+    /// How long a vanished directory is waited for to turn up under a new
+    /// name before it is reported removed.
+    pub fn rename_window(mut self, window: Duration) -> Root {
+        self.rename_window = Some(window);
+        self
+    }
+}
+
+/// Settings for a single watched root, with every default filled in.
+#[derive(Debug, Clone)]
+pub(crate) struct RootSettings {
+    pub name: String,
+    pub path: PathBuf,
+    pub backend: Backend,
+    pub poll_interval: Duration,
+    pub depth: Depth,
+    pub ignore: Filter,
+    pub placeholders: Filter,
+    pub search_path: PathBuf,
+    pub search_roots: Vec<PathBuf>,
+    pub detect_trash: bool,
+    pub shadow: Option<PathBuf>,
+    pub shadow_retention: Duration,
+    pub manifest_entries: usize,
+    pub rename_window: Duration,
+}
+
+/// Configures and starts a [`DirMonitor`].
+#[derive(Debug)]
+pub struct DirMonitorBuilder {
+    roots: Vec<Root>,
+    backend: Backend,
+    poll_interval: Duration,
+    depth: Depth,
+    ignore: Filter,
+    placeholders: Filter,
+    search_roots: Vec<PathBuf>,
+    detect_trash: bool,
+    shadow: Option<PathBuf>,
+    shadow_retention: Duration,
+    manifest_entries: usize,
+    rename_window: Duration,
+    state_file: Option<PathBuf>,
+}
+
+impl Default for DirMonitorBuilder {
+    fn default() -> DirMonitorBuilder {
+        DirMonitorBuilder {
+            roots: Vec::new(),
+            backend: Backend::default(),
+            poll_interval: DEFAULT_POLL_INTERVAL,
+            depth: Depth::default(),
+            ignore: Filter::default_ignore(),
+            placeholders: Filter::default_placeholders(),
+            search_roots: Vec::new(),
+            detect_trash: true,
+            shadow: None,
+            shadow_retention: shadow::DEFAULT_RETENTION,
+            manifest_entries: 0,
+            rename_window: DEFAULT_RENAME_WINDOW,
+            state_file: None,
+        }
+    }
+}
+
+impl DirMonitorBuilder {
+    pub fn root(mut self, root: Root) -> DirMonitorBuilder {
+        self.roots.push(root);
+        self
+    }
+
+    /// Shorthand for adding a root with default settings.
+    pub fn watch(self, path: impl Into<PathBuf>) -> DirMonitorBuilder {
+        self.root(Root::new(path))
+    }
+
+    /// Backend for roots that do not choose one; defaults to `Auto`.
+    pub fn backend(mut self, backend: Backend) -> DirMonitorBuilder {
+        self.backend = backend;
+        self
+    }
+
+    /// Poll interval for roots that do not set one; defaults to 60 seconds.
+    pub fn poll_interval(mut self, interval: Duration) -> DirMonitorBuilder {
+        self.poll_interval = interval;
+        self
+    }
+
+    /// Depth for roots that do not set one; defaults to the top level only.
+    pub fn depth(mut self, depth: Depth) -> DirMonitorBuilder {
+        self.depth = depth;
+        self
+    }
+
+    /// Ignore rules for roots that do not set their own.
+    pub fn ignore(mut self, filter: Filter) -> DirMonitorBuilder {
+        self.ignore = filter;
+        self
+    }
+
+    /// Placeholder names for roots that do not set their own.
+    pub fn placeholders(mut self, filter: Filter) -> DirMonitorBuilder {
+        self.placeholders = filter;
+        self
+    }
+
+    /// Search roots for roots that do not set their own; none by default.
+    pub fn search_roots(mut self, paths: Vec<PathBuf>) -> DirMonitorBuilder {
+        self.search_roots = paths;
+        self
+    }
+
+    /// Trash detection for roots that do not set it; on by default.
+    pub fn detect_trash(mut self, detect: bool) -> DirMonitorBuilder {
+        self.detect_trash = detect;
+        self
+    }
+
+    /// Shadow directory for roots that do not set one; none by default.
+    pub fn shadow(mut self, dir: impl Into<PathBuf>) -> DirMonitorBuilder {
+        self.shadow = Some(dir.into());
+        self
+    }
+
+    /// Snapshot retention for roots that do not set one; defaults to 30
+    /// days.
+    pub fn shadow_retention(mut self, retention: Duration) -> DirMonitorBuilder {
+        self.shadow_retention = retention;
+        self
+    }
+
+    /// Manifest entries for roots that do not set them; none by default, so
+    /// manifests only hold totals.
+    pub fn manifest_entries(mut self, entries: usize) -> DirMonitorBuilder {
+        self.manifest_entries = entries;
+        self
+    }
+
+    /// Rename window for roots that do not set one; defaults to 2 seconds.
+    pub fn rename_window(mut self, window: Duration) -> DirMonitorBuilder {
+        self.rename_window = window;
+        self
+    }
+
+    /// Saves the known directories to `path` and, on the next start, reports
+    /// what changed in between.
+    pub fn state_file(mut self, path: impl Into<PathBuf>) -> DirMonitorBuilder {
+        self.state_file = Some(path.into());
+        self
+    }
+
+    /// Scans every root and starts watching it.
+    pub fn build(self) -> Result<DirMonitor, Error> {
+        if self.roots.is_empty() {
+            return Err(Error::Invalid("no roots to monitor".to_string()));
+        }
+        let mut saved = match &self.state_file {
+            Some(path) => State::load(path)?,
+            None => State::default(),
+        };
+
+        let (tx, rx) = mpsc::channel();
+        let mut names = HashSet::new();
+        let mut roots = Vec::new();
+        for (index, root) in self.roots.into_iter().enumerate() {
+            let settings = RootSettings {
+                name: root.name.unwrap_or_else(|| root.path.display().to_string()),
+                backend: root.backend.unwrap_or(self.backend),
+                poll_interval: root.poll_interval.unwrap_or(self.poll_interval),
+                depth: root.depth.unwrap_or(self.depth),
+                ignore: root.ignore.unwrap_or_else(|| self.ignore.clone()),
+                placeholders: root
+                    .placeholders
+                    .unwrap_or_else(|| self.placeholders.clone()),
+                search_path: root.search_path.unwrap_or_else(|| root.path.clone()),
+                search_roots: root
+                    .search_roots
+                    .unwrap_or_else(|| self.search_roots.clone()),
+                detect_trash: root.detect_trash.unwrap_or(self.detect_trash),
+                shadow: root.shadow.or_else(|| self.shadow.clone()),
+                shadow_retention: root.shadow_retention.unwrap_or(self.shadow_retention),
+                manifest_entries: root.manifest_entries.unwrap_or(self.manifest_entries),
+                rename_window: root.rename_window.unwrap_or(self.rename_window),
+                path: root.path,
+            };
+            if !names.insert(settings.name.clone()) {
+                return Err(Error::Invalid(format!(
+                    "root name '{}' is used more than once",
+                    settings.name
+                )));
+            }
+            if settings.depth == Depth::Levels(0) {
+                return Err(Error::Invalid(format!(
+                    "depth for {} must be at least 1",
+                    settings.path.display()
+                )));
+            }
+            if settings.poll_interval.is_zero() {
+                return Err(Error::Invalid(format!(
+                    "poll interval for {} must not be zero",
+                    settings.path.display()
+                )));
+            }
+
+            let mut monitor = RootMonitor::start(index, settings, tx.clone())?;
+            if let Some(root_state) = saved.roots.remove(monitor.name()) {
+                monitor.reconcile(root_state);
+            }
+            monitor.prune_shadow();
+            monitor.note_first_sight();
+            roots.push(monitor);
+        }
+
+        let mut monitor = DirMonitor {
+            roots,
+            rx,
+            pending: VecDeque::new(),
+            state_file: self.state_file,
+            last_saved: Instant::now(),
+            save_interval: STATE_SAVE_INTERVAL,
+            save_failing: false,
+            unsaved: true,
+        };
+        monitor.collect_events();
+        monitor.save_state();
+        Ok(monitor)
+    }
+}
+
+/// Watches one or more roots and reports changes to their directories.
+///
+/// Events are read by iterating over the monitor, which blocks until the
+/// next one is available.
+///
+/// ```no_run
+/// use dirmon::{DirEvent, DirMonitor};
+///
+/// let monitor = DirMonitor::builder().watch("/srv/share").build()?;
+/// for event in monitor {
+///     if let DirEvent::Moved { from, to, .. } = event {
+///         println!("{} -> {}", from.display(), to.display());
+///     }
+/// }
+/// # Ok::<(), dirmon::Error>(())
+/// ```
+pub struct DirMonitor {
+    roots: Vec<RootMonitor>,
+    rx: Receiver<TaggedEvent>,
+    pending: VecDeque<DirEvent>,
+    state_file: Option<PathBuf>,
+    last_saved: Instant,
+    /// Wait before the next save, longer while saves fail
+    save_interval: Duration,
+    /// Set once a failed save has been reported, until one succeeds
+    save_failing: bool,
+    unsaved: bool,
+}
+
+impl DirMonitor {
+    pub fn builder() -> DirMonitorBuilder {
+        DirMonitorBuilder::default()
+    }
+
+    /// Path of the root called `name`.
+    pub fn root_path(&self, name: &str) -> Option<&Path> {
+        self.roots
+            .iter()
+            .find(|root| root.name() == name)
+            .map(|root| root.path())
+    }
+
+    /// Waits up to `timeout` for the next event.
+    pub fn next_timeout(&mut self, timeout: Duration) -> Option<DirEvent> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            if !self.process(TICK.min(deadline - now)) {
+                return None;
+            }
+        }
+    }
+
+    /// Handles watcher events for up to `timeout`. Returns false once every
+    /// watcher has stopped.
+    fn process(&mut self, timeout: Duration) -> bool {
+        let running = match self.rx.recv_timeout(timeout) {
+            Ok((index, event)) => {
+                self.roots[index].handle(event);
+                true
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => false,
+        };
+        for root in &mut self.roots {
+            root.expire_pending();
+        }
+        self.collect_events();
+        if self.unsaved && self.last_saved.elapsed() >= self.save_interval {
+            self.save_state();
+        }
+        running
+    }
+
+    fn collect_events(&mut self) {
+        for root in &mut self.roots {
+            self.unsaved |= root.take_dirty();
+            self.pending.extend(root.take_events());
+        }
+    }
+
+    fn save_state(&mut self) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+        let state = State {
+            roots: self
+                .roots
+                .iter()
+                .map(|root| (root.name().to_string(), root.root_state()))
+                .collect(),
+        };
+        self.last_saved = Instant::now();
+        // A failed save is retried later and later, and reported once until
+        // one succeeds
+        if let Err(e) = state.save(path) {
+            self.save_interval = (self.save_interval * 2).min(STATE_SAVE_MAX_BACKOFF);
+            if !self.save_failing {
+                self.save_failing = true;
+                // The state file belongs to every root, so each one's sinks
+                // hear about it
+                let message = e.to_string();
+                for root in &mut self.roots {
+                    root.emit_error(message.clone());
+                }
+                self.collect_events();
+            }
+            return;
+        }
+        self.save_interval = STATE_SAVE_INTERVAL;
+        self.save_failing = false;
+        self.unsaved = false;
+    }
+}
+
+impl Drop for DirMonitor {
+    /// Saves changes not saved yet, so they are not reported again as made
+    /// while no monitor was running.
+    fn drop(&mut self) {
+        if self.unsaved {
+            self.save_state();
+        }
+    }
+}
+
+impl Iterator for DirMonitor {
+    type Item = DirEvent;
+
+    fn next(&mut self) -> Option<DirEvent> {
+        loop {
+            if let Some(event) = self.pending.pop_front() {
+                return Some(event);
+            }
+            if !self.process(TICK) {
+                return self.pending.pop_front();
+            }
+        }
+    }
+}
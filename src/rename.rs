@@ -0,0 +1,54 @@
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+/// Source halves of native rename events waiting for their destination.
+///
+/// inotify reports a rename as `From`, `To` and finally `Both`, all sharing
+/// a tracker cookie. A `From` that never sees its `To` was moved outside the
+/// watched tree.
+pub struct PendingRenames {
+    pending: HashMap<usize, (PathBuf, Instant)>,
+    /// How long a source waits for its destination before it is treated as
+    /// a removal
+    window: Duration,
+}
+
+impl PendingRenames {
+    pub fn new(window: Duration) -> PendingRenames {
+        PendingRenames {
+            pending: HashMap::new(),
+            window,
+        }
+    }
+
+    pub fn insert(&mut self, tracker: usize, from: PathBuf) {
+        self.pending.insert(tracker, (from, Instant::now()));
+    }
+
+    pub fn contains(&self, tracker: usize) -> bool {
+        self.pending.contains_key(&tracker)
+    }
+
+    pub fn take(&mut self, tracker: usize) -> Option<PathBuf> {
+        self.pending.remove(&tracker).map(|(from, _)| from)
+    }
+
+    /// Removes and returns the sources that have waited longer than the
+    /// window, with when they were seen.
+    pub fn take_expired(&mut self) -> Vec<(PathBuf, Instant)> {
+        let now = Instant::now();
+        let expired: Vec<usize> = self
+            .pending
+            .iter()
+            .filter(|(_, (_, seen))| now.duration_since(*seen) >= self.window)
+            .map(|(tracker, _)| *tracker)
+            .collect();
+        expired
+            .into_iter()
+            .filter_map(|tracker| self.pending.remove(&tracker))
+            .collect()
+    }
+}
@@ -0,0 +1,410 @@
+use crate::{event::DirEvent, identity::DirIdentity, index::DirIndex, snapshot::DirSnapshot};
+use std::{
+    cmp::Ordering,
+    path::{Path, PathBuf},
+};
+use walkdir::WalkDir;
+
+/// Same-named directories scoring below this are treated as unrelated.
+const MIN_CONFIDENCE: f64 = 0.5;
+
+/// Directories under another name must look nearly identical to be taken
+/// for the vanished one.
+const MIN_FINGERPRINT_SIMILARITY: f64 = 0.8;
+
+/// A directory that may be where a vanished one went.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub confidence: f64,
+}
+
+/// Where a vanished directory went, as far as can be told.
+#[derive(Debug)]
+pub enum MoveResolution {
+    /// The directory itself, found by identity
+    Exact(PathBuf),
+    /// The only plausible same-named directory, or the only one with
+    /// nearly the same contents
+    Likely(Candidate),
+    /// Several plausible directories, best first
+    Ambiguous(Vec<Candidate>),
+    NotFound,
+}
+
+impl MoveResolution {
+    /// Where the directory is now tracked, if it was found.
+    pub fn new_path(&self) -> Option<&Path> {
+        match self {
+            MoveResolution::Exact(path) => Some(path),
+            MoveResolution::Likely(candidate) => Some(&candidate.path),
+            MoveResolution::Ambiguous(_) | MoveResolution::NotFound => None,
+        }
+    }
+
+    /// The event for the move from `from`; `None` if nothing was found.
+    pub fn event(&self, root: &str, from: &Path, offline: bool) -> Option<DirEvent> {
+        match self {
+            MoveResolution::Exact(path) => Some(DirEvent::moved(root, from, path, None, offline)),
+            MoveResolution::Likely(c) => Some(DirEvent::moved(
+                root,
+                from,
+                &c.path,
+                Some(c.confidence),
+                offline,
+            )),
+            MoveResolution::Ambiguous(candidates) => Some(DirEvent::AmbiguousMove {
+                root: root.to_string(),
+                from: from.to_path_buf(),
+                candidates: candidates
+                    .iter()
+                    .map(|c| (c.path.clone(), c.confidence))
+                    .collect(),
+                manifest: None,
+                offline,
+            }),
+            MoveResolution::NotFound => None,
+        }
+    }
+
+    /// Like [`MoveResolution::event`], for a directory found outside the
+    /// root.
+    pub fn moved_out_event(&self, root: &str, from: &Path, offline: bool) -> Option<DirEvent> {
+        let (to, confidence) = match self {
+            MoveResolution::Exact(path) => (path, None),
+            MoveResolution::Likely(c) => (&c.path, Some(c.confidence)),
+            _ => return self.event(root, from, offline),
+        };
+        Some(DirEvent::MovedOut {
+            root: root.to_string(),
+            from: from.to_path_buf(),
+            to: to.clone(),
+            confidence,
+            manifest: None,
+            offline,
+        })
+    }
+}
+
+/// Looks for the directory that vanished from `old_path` somewhere under
+/// `search_path`. Whatever is at `old_path` now is never the answer.
+///
+/// A directory with the remembered identity is the answer whatever its name
+/// now is. Failing that, directories with the same name are scored against
+/// the snapshot; `is_known` excludes directories that are tracked in their
+/// own right. When the snapshot has an identity, a same-named directory that
+/// was already there before it vanished has another identity and cannot be
+/// it, so only the ones `is_new` holds for, which appeared since, are
+/// scored.
+///
+/// Both lookups go through `index`. Only a search path reaching beyond the
+/// indexed tree is walked, and only when the identity is not in the index.
+pub fn resolve_move(
+    old_path: &Path,
+    snapshot: &DirSnapshot,
+    index: &DirIndex,
+    search_path: &Path,
+    is_known: impl Fn(&Path) -> bool,
+    is_new: impl Fn(&Path) -> bool,
+) -> MoveResolution {
+    if let Some(identity) = &snapshot.identity {
+        if let Some(path) = index.find_identity(identity) {
+            // The index may lag behind the disk by a few events
+            if path != old_path
+                && path.starts_with(search_path)
+                && DirIdentity::of(path).as_ref() == Some(identity)
+            {
+                return MoveResolution::Exact(path.to_path_buf());
+            }
+        }
+    }
+    if !index.covers(search_path) {
+        return resolve_by_walk(old_path, snapshot, search_path, is_known);
+    }
+
+    let dir_name = old_path.file_name().unwrap_or_default();
+    let same_name = index
+        .named(dir_name)
+        .filter(|path| {
+            *path != old_path
+                && path.starts_with(search_path)
+                && !is_known(path)
+                && (snapshot.identity.is_none() || is_new(path))
+                && path.is_dir()
+        })
+        .map(Path::to_path_buf)
+        .collect();
+    score(snapshot, same_name)
+}
+
+/// Like [`resolve_move`], but walks `search_path` instead of looking the
+/// directory up.
+pub fn resolve_by_walk(
+    old_path: &Path,
+    snapshot: &DirSnapshot,
+    search_path: &Path,
+    is_known: impl Fn(&Path) -> bool,
+) -> MoveResolution {
+    let dir_name = old_path.file_name().unwrap_or_default();
+    let mut same_name = Vec::new();
+
+    let dirs = WalkDir::new(search_path)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_dir());
+    for entry in dirs {
+        if snapshot.identity.is_some() {
+            let identity = entry
+                .metadata()
+                .ok()
+                .and_then(|m| DirIdentity::from_metadata(&m));
+            if identity == snapshot.identity && entry.path() != old_path {
+                return MoveResolution::Exact(entry.into_path());
+            }
+        }
+        if entry.file_name() == dir_name && entry.path() != old_path && !is_known(entry.path()) {
+            same_name.push(entry.into_path());
+        }
+    }
+    score(snapshot, same_name)
+}
+
+/// Searches `search_roots` in order for a directory that left the root,
+/// stopping at the first that has a plausible match.
+pub fn resolve_outside(
+    old_path: &Path,
+    snapshot: &DirSnapshot,
+    search_roots: &[PathBuf],
+) -> MoveResolution {
+    for search_root in search_roots {
+        let resolution = resolve_by_walk(old_path, snapshot, search_root, |_| false);
+        if !matches!(resolution, MoveResolution::NotFound) {
+            return resolution;
+        }
+    }
+    MoveResolution::NotFound
+}
+
+/// Looks among `arrivals`, directories that appeared around the time the
+/// one at `snapshot` vanished, for one whose contents match its fingerprint
+/// closely enough, whatever it is called.
+pub fn resolve_by_fingerprint(snapshot: &DirSnapshot, arrivals: Vec<PathBuf>) -> MoveResolution {
+    if snapshot.fingerprint.is_empty() {
+        return MoveResolution::NotFound;
+    }
+    let candidates = arrivals
+        .into_iter()
+        .map(|path| Candidate {
+            confidence: snapshot
+                .fingerprint
+                .similarity(&DirSnapshot::take(&path).fingerprint),
+            path,
+        })
+        .filter(|c| c.confidence >= MIN_FINGERPRINT_SIMILARITY)
+        .collect();
+    rank(candidates)
+}
+
+/// Whether `new` holds nearly the same files as `old`, by name and size, so
+/// it may be `old` under another name or a copy of it.
+pub fn same_contents(old: &DirSnapshot, new: &DirSnapshot) -> bool {
+    !old.fingerprint.is_empty()
+        && old.fingerprint.content_similarity(&new.fingerprint) >= MIN_FINGERPRINT_SIMILARITY
+}
+
+/// Ranks same-named directories by how much they look like `snapshot`.
+fn score(snapshot: &DirSnapshot, same_name: Vec<PathBuf>) -> MoveResolution {
+    let candidates = same_name
+        .into_iter()
+        .map(|path| Candidate {
+            confidence: snapshot.similarity(&DirSnapshot::take(&path)),
+            path,
+        })
+        .filter(|c| c.confidence >= MIN_CONFIDENCE)
+        .collect();
+    rank(candidates)
+}
+
+/// Orders plausible candidates best first and tells whether one stands out.
+fn rank(mut candidates: Vec<Candidate>) -> MoveResolution {
+    candidates.sort_by(|a, b| {
+        b.confidence
+            .partial_cmp(&a.confidence)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| a.path.cmp(&b.path))
+    });
+
+    match candidates.len() {
+        0 => MoveResolution::NotFound,
+        1 => MoveResolution::Likely(candidates.remove(0)),
+        _ => MoveResolution::Ambiguous(candidates),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fingerprint::ChildPrint;
+    use std::fs;
+
+    /// A directory of its own, removed first if a previous run left it
+    /// behind.
+    fn base(name: &str) -> PathBuf {
+        let base =
+            std::env::temp_dir().join(format!("dirmon-resolve-{}-{}", std::process::id(), name));
+        let _ = fs::remove_dir_all(&base);
+        fs::create_dir_all(&base).unwrap();
+        base
+    }
+
+    /// Creates `dir` holding one-byte files with the given names.
+    fn make(dir: &Path, files: &[&str]) {
+        fs::create_dir_all(dir).unwrap();
+        for file in files {
+            fs::write(dir.join(file), b"x").unwrap();
+        }
+    }
+
+    /// What was remembered of a directory holding one-byte files with the
+    /// given names, without an identity to find it by.
+    fn remembered(files: &[&str]) -> DirSnapshot {
+        let mut snapshot = DirSnapshot {
+            size: files.len() as u64,
+            ..DirSnapshot::default()
+        };
+        for file in files {
+            snapshot
+                .fingerprint
+                .insert(file.to_string(), ChildPrint::new(Some(1), None));
+        }
+        snapshot
+    }
+
+    fn candidate(path: &str, confidence: f64) -> Candidate {
+        Candidate {
+            path: PathBuf::from(path),
+            confidence,
+        }
+    }
+
+    #[test]
+    fn rank_orders_best_first_then_by_path() {
+        let ranked = rank(vec![
+            candidate("b", 0.6),
+            candidate("c", 0.9),
+            candidate("a", 0.6),
+        ]);
+        let MoveResolution::Ambiguous(candidates) = ranked else {
+            panic!("expected an ambiguous move, got {:?}", ranked);
+        };
+        let order: Vec<&Path> = candidates.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(order, [Path::new("c"), Path::new("a"), Path::new("b")]);
+    }
+
+    #[test]
+    fn rank_tells_one_candidate_from_none() {
+        assert!(matches!(rank(Vec::new()), MoveResolution::NotFound));
+        assert!(matches!(
+            rank(vec![candidate("a", 0.5)]),
+            MoveResolution::Likely(c) if c.path == Path::new("a")
+        ));
+    }
+
+    #[test]
+    fn namesakes_below_min_confidence_are_dropped() {
+        let base = base("confidence");
+        let files = ["a", "b", "c", "d"];
+        make(&base.join("x/Acme"), &files);
+        // One child of four and a quarter of the size scores 0.2
+        make(&base.join("y/Acme"), &["a"]);
+        make(&base.join("z/Acme"), &[]);
+
+        let resolution = resolve_by_walk(&base.join("Acme"), &remembered(&files), &base, |_| false);
+        let MoveResolution::Likely(found) = resolution else {
+            panic!("expected a likely move, got {:?}", resolution);
+        };
+        assert_eq!(found.path, base.join("x/Acme"));
+        assert!(found.confidence >= MIN_CONFIDENCE);
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn alike_namesakes_make_an_ambiguous_move() {
+        let base = base("ambiguous");
+        let files = ["a", "b"];
+        make(&base.join("y/Acme"), &files);
+        make(&base.join("x/Acme"), &files);
+        make(&base.join("z/Acme"), &["a"]);
+
+        let resolution = resolve_by_walk(&base.join("Acme"), &remembered(&files), &base, |_| false);
+        let MoveResolution::Ambiguous(candidates) = resolution else {
+            panic!("expected an ambiguous move, got {:?}", resolution);
+        };
+        let paths: Vec<&Path> = candidates.iter().map(|c| c.path.as_path()).collect();
+        assert_eq!(paths, [base.join("x/Acme"), base.join("y/Acme")]);
+        assert!(candidates.iter().all(|c| c.confidence >= MIN_CONFIDENCE));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn known_directories_and_the_old_path_are_never_candidates() {
+        let base = base("excluded");
+        let files = ["a", "b"];
+        make(&base.join("Acme"), &files);
+        make(&base.join("x/Acme"), &files);
+        make(&base.join("y/Acme"), &files);
+        let known = base.join("y/Acme");
+
+        let resolution = resolve_by_walk(&base.join("Acme"), &remembered(&files), &base, |path| {
+            path == known
+        });
+        assert_eq!(resolution.new_path(), Some(base.join("x/Acme").as_path()));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn renamed_copy_must_nearly_match_the_fingerprint() {
+        let base = base("fingerprint");
+        let files = ["a", "b", "c", "d", "e"];
+        make(&base.join("Copy"), &files);
+        // Four of six children in common scores below the threshold
+        make(&base.join("Other"), &["a", "b", "c", "d", "f"]);
+        let mut snapshot = DirSnapshot::take(&base.join("Copy"));
+        snapshot.identity = None;
+
+        let arrivals = vec![base.join("Copy"), base.join("Other")];
+        let resolution = resolve_by_fingerprint(&snapshot, arrivals);
+        let MoveResolution::Likely(found) = resolution else {
+            panic!("expected a likely move, got {:?}", resolution);
+        };
+        assert_eq!(found.path, base.join("Copy"));
+        assert_eq!(found.confidence, 1.0);
+
+        let empty = DirSnapshot::default();
+        let arrivals = vec![base.join("Copy")];
+        assert!(matches!(
+            resolve_by_fingerprint(&empty, arrivals),
+            MoveResolution::NotFound
+        ));
+        fs::remove_dir_all(&base).unwrap();
+    }
+
+    #[test]
+    fn identity_wins_over_a_namesake() {
+        let base = base("identity");
+        make(&base.join("Renamed"), &[]);
+        make(&base.join("x/Acme"), &["a"]);
+        let snapshot = DirSnapshot {
+            identity: DirIdentity::of(&base.join("Renamed")),
+            ..remembered(&["a"])
+        };
+
+        let resolution = resolve_by_walk(&base.join("Acme"), &snapshot, &base, |_| false);
+        assert!(matches!(
+            &resolution,
+            MoveResolution::Exact(path) if *path == base.join("Renamed")
+        ));
+        fs::remove_dir_all(&base).unwrap();
+    }
+}
@@ -0,0 +1,1750 @@
+use crate::{
+    backend::Backend,
+    error::Error,
+    event::DirEvent,
+    identity::DirIdentity,
+    index::DirIndex,
+    lineage::{self, Step},
+    manifest::{Manifest, Totals},
+    monitor::{Depth, RootSettings},
+    rename::PendingRenames,
+    resolve::{
+        resolve_by_fingerprint, resolve_move, resolve_outside, same_contents, MoveResolution,
+    },
+    shadow::Shadow,
+    snapshot::DirSnapshot,
+    state::RootState,
+    trash::{find_trashed, trash_directories, TrashInfo},
+    tree::DirTree,
+};
+use notify::{
+    event::{DataChange, MetadataKind, ModifyKind, RenameMode},
+    Event, EventKind, RecursiveMode, Watcher,
+};
+use std::{
+    cmp::Ordering,
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    path::{Component, Path, PathBuf},
+    sync::mpsc::Sender,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use walkdir::WalkDir;
+
+/// How long changes wait before they are handled, so that a tree created or
+/// deleted in one go is reported once, for its top.
+const SETTLE_DELAY: Duration = Duration::from_millis(300);
+
+/// How long a new path is considered as where a vanished directory may have
+/// gone, when that cannot be told from its identity.
+const ARRIVAL_WINDOW: Duration = Duration::from_secs(60);
+
+/// How long the contents of a directory too deep to be tracked must be left
+/// alone before its files are counted again, so a copy or deletion in
+/// progress is read through once it is done rather than at every file.
+const MANIFEST_QUIET: Duration = Duration::from_secs(2);
+
+/// Minimum time between two checks for lineages past their retention.
+const LINEAGE_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// A change waiting for the tree to settle.
+enum Change {
+    Created(PathBuf),
+    /// The immediate contents of a tracked directory changed. Re-reading it
+    /// only once things settle keeps the snapshot of a directory that is
+    /// being deleted from before its contents went.
+    Modified(PathBuf),
+}
+
+/// What is known about how a directory vanished, for telling where it went.
+struct Departure<'a> {
+    /// Paths that appeared meanwhile, which are compared by content when
+    /// neither the identity nor the name leads anywhere
+    arrivals: &'a [PathBuf],
+    /// Those that appeared after it vanished, the only same-named
+    /// directories that can be it when it had an identity
+    since: &'a [PathBuf],
+    /// When it was last known to be in place
+    last_seen: SystemTime,
+    offline: bool,
+}
+
+/// Watcher events tagged with the index of the root they came from.
+pub type TaggedEvent = (usize, notify::Result<Event>);
+
+/// Tracks the directories of one watched root, down to the configured depth.
+pub(crate) struct RootMonitor {
+    settings: RootSettings,
+    /// Tracked directories and what they looked like when last seen
+    known_directories: DirTree,
+    /// Every directory under the root, tracked or not, where vanished ones
+    /// are looked for
+    index: DirIndex,
+    /// Freshly created folders still carrying a file manager's default name;
+    /// they are logged once they get a real one
+    placeholders: HashMap<PathBuf, DirSnapshot>,
+    pending_renames: PendingRenames,
+    /// Hardlinked mirror of the root, when one is kept
+    shadow: Option<Shadow>,
+    /// Creations and content changes in the order they were seen
+    settling: VecDeque<(Change, Instant)>,
+    /// Removals, which wait out the rename window in case the directory
+    /// turns up under a new name
+    vanishing: VecDeque<(PathBuf, Instant)>,
+    /// Paths created or moved in lately, with when they were seen
+    arrivals: HashMap<PathBuf, Instant>,
+    /// Directories that appeared as copies of a tracked one, with the
+    /// original; they are never where a vanished directory went
+    copies: HashMap<PathBuf, PathBuf>,
+    /// Subdirectories counted in their tracked parent whose contents changed
+    /// since, with when they last changed
+    stale_subdirs: HashMap<PathBuf, Instant>,
+    /// Where each directory tracked so far has been, by id
+    lineage: BTreeMap<String, Vec<Step>>,
+    lineage_checked: Instant,
+    /// Events not yet handed out
+    events: Vec<DirEvent>,
+    /// Set when the known directories or their lineage changed since the
+    /// state was last saved
+    dirty: bool,
+    // Native backends report absolute paths; this maps them back under the
+    // root as it was configured
+    absolute_root: PathBuf,
+    // Kept alive for as long as the root is monitored
+    _watcher: Box<dyn Watcher>,
+}
+
+impl RootMonitor {
+    /// Starts watching the root and scans the directories in it.
+    ///
+    /// Events are sent to `tx` tagged with `index`.
+    pub fn start(
+        index: usize,
+        settings: RootSettings,
+        tx: Sender<TaggedEvent>,
+    ) -> Result<RootMonitor, Error> {
+        let watch_path = settings.path.as_path();
+        std::fs::read_dir(watch_path).map_err(|source| Error::Io {
+            path: watch_path.to_path_buf(),
+            source,
+        })?;
+
+        let absolute_root = std::path::absolute(watch_path).map_err(|source| Error::Io {
+            path: watch_path.to_path_buf(),
+            source,
+        })?;
+        let watch = |backend: Backend| {
+            let tx = tx.clone();
+            let handler = move |event| {
+                let _ = tx.send((index, event));
+            };
+            let mut watcher = backend.create_watcher(settings.poll_interval, handler)?;
+            watcher.watch(watch_path, RecursiveMode::Recursive)?;
+            notify::Result::Ok(watcher)
+        };
+        let backend = settings.backend.resolve(watch_path);
+        // When the backend was left to dirmon, running out of kernel watches
+        // or the like is no reason not to monitor the root
+        let mut fell_back = None;
+        let watcher = match watch(backend) {
+            Err(error) if settings.backend == Backend::Auto && backend == Backend::Native => {
+                fell_back = Some(format!(
+                    "cannot watch with kernel notifications ({}); polling instead",
+                    error
+                ));
+                watch(Backend::Poll)
+            }
+            result => result,
+        }
+        .map_err(|source| Error::Watch {
+            path: watch_path.to_path_buf(),
+            source,
+        })?;
+
+        let shadow = match &settings.shadow {
+            Some(dir) => Some(Shadow::open(
+                dir,
+                &settings.path,
+                &absolute_root,
+                settings.shadow_retention,
+            )?),
+            None => None,
+        };
+
+        let events = vec![DirEvent::Started {
+            root: settings.name.clone(),
+        }];
+        let pending_renames = PendingRenames::new(settings.rename_window);
+        let mut monitor = RootMonitor {
+            known_directories: DirTree::new(settings.path.clone()),
+            index: DirIndex::build(
+                &settings.path,
+                shadow.as_ref().and_then(Shadow::within_root),
+            ),
+            settings,
+            placeholders: HashMap::new(),
+            pending_renames,
+            shadow,
+            settling: VecDeque::new(),
+            vanishing: VecDeque::new(),
+            arrivals: HashMap::new(),
+            copies: HashMap::new(),
+            stale_subdirs: HashMap::new(),
+            lineage: BTreeMap::new(),
+            lineage_checked: Instant::now(),
+            events,
+            dirty: false,
+            absolute_root,
+            _watcher: watcher,
+        };
+        // Changes made during the scan are queued up by the watcher already
+        if let Some(message) = fell_back {
+            monitor.emit_error(message);
+        }
+        let root = monitor.settings.path.clone();
+        monitor.track_below(&root);
+        monitor.shadow_sync(&root);
+        Ok(monitor)
+    }
+
+    /// Hands out the events produced since the last call.
+    pub fn take_events(&mut self) -> std::vec::Drain<'_, DirEvent> {
+        self.events.drain(..)
+    }
+
+    fn emit(&mut self, event: DirEvent) {
+        self.events.push(event);
+    }
+
+    pub fn emit_error(&mut self, message: String) {
+        let root = self.settings.name.clone();
+        self.emit(DirEvent::Error { root, message });
+    }
+
+    pub fn name(&self) -> &str {
+        &self.settings.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.settings.path
+    }
+
+    /// Copies the known directories out for saving.
+    pub fn root_state(&self) -> RootState {
+        RootState {
+            path: self.settings.path.clone(),
+            absolute_path: Some(self.absolute_root.clone()),
+            depth: self.settings.depth.max_level(),
+            directories: self
+                .known_directories
+                .entries()
+                .into_iter()
+                .map(|(path, snapshot)| (path, snapshot.clone()))
+                .collect(),
+            saved_at: Some(SystemTime::now()),
+            lineage: self.lineage.clone(),
+        }
+    }
+
+    /// Returns whether the state changed since the last call.
+    pub fn take_dirty(&mut self) -> bool {
+        std::mem::take(&mut self.dirty)
+    }
+
+    /// Reports what changed while no monitor was running, by comparing the
+    /// directories saved by the last run with the ones found at start.
+    ///
+    /// Only levels tracked by both runs are compared, and a change to a whole
+    /// tree is reported for its top directory only.
+    pub fn reconcile(&mut self, mut saved: RootState) {
+        if saved.path != self.settings.path {
+            return;
+        }
+        self.lineage = std::mem::take(&mut saved.lineage);
+        let saved_depth = match saved.depth {
+            Some(levels) => Depth::Levels(levels),
+            None => Depth::Unlimited,
+        };
+
+        // Directories on disk that the last run did not know in that place
+        let mut appeared: HashSet<PathBuf> = self
+            .known_directories
+            .entries()
+            .into_iter()
+            .filter(|(path, current)| {
+                self.level(path)
+                    .is_some_and(|level| saved_depth.includes(level))
+                    && saved
+                        .directories
+                        .get(path)
+                        .is_none_or(|old| old.identity != current.identity)
+            })
+            .map(|(path, _)| path)
+            .collect();
+
+        // Directories still where they were keep their ids
+        for (path, old) in &saved.directories {
+            if appeared.contains(path) {
+                continue;
+            }
+            if let Some(current) = self.known_directories.get_mut(path) {
+                if current.identity == old.identity {
+                    current.id.clone_from(&old.id);
+                }
+            }
+        }
+
+        let vanished_paths: HashSet<&PathBuf> = saved
+            .directories
+            .iter()
+            .filter(|(path, old)| {
+                self.level(path)
+                    .is_some_and(|level| self.settings.depth.includes(level))
+                    && self
+                        .known_directories
+                        .get(path)
+                        .is_none_or(|current| current.identity != old.identity)
+            })
+            .map(|(path, _)| path)
+            .collect();
+        let mut vanished: Vec<(&PathBuf, &DirSnapshot)> = vanished_paths
+            .iter()
+            .filter(|path| {
+                !path
+                    .parent()
+                    .is_some_and(|p| vanished_paths.contains(&p.to_path_buf()))
+            })
+            .map(|path| (*path, &saved.directories[*path]))
+            .collect();
+        vanished.sort_by(|a, b| a.0.cmp(b.0));
+
+        for (path, snapshot) in vanished {
+            let arrivals: Vec<PathBuf> = appeared.iter().cloned().collect();
+            let departure = Departure {
+                arrivals: &arrivals,
+                since: &arrivals,
+                last_seen: saved.saved_at.or(snapshot.mtime).unwrap_or(UNIX_EPOCH),
+                offline: true,
+            };
+            let (resolution, event) = self.locate(path, snapshot, &departure, |p| {
+                self.known_directories.contains(p) && !appeared.contains(p)
+            });
+            if let Some(new_path) = resolution.new_path() {
+                appeared.retain(|p| !p.starts_with(new_path));
+            }
+            let event = match event {
+                Some(event) => event,
+                None if self.is_ignored(path) => continue,
+                None => DirEvent::Removed {
+                    root: self.settings.name.clone(),
+                    path: path.clone(),
+                    snapshot: self.keep_shadow(path),
+                    manifest: None,
+                    offline: true,
+                },
+            };
+            let mut moved: Vec<(PathBuf, DirSnapshot)> = saved
+                .directories
+                .iter()
+                .filter(|(old, _)| old.starts_with(path))
+                .map(|(old, snapshot)| (old.clone(), snapshot.clone()))
+                .collect();
+            moved.sort_by(|a, b| a.0.cmp(&b.0));
+            let event = event.with_manifest(self.manifest(&moved));
+            self.record(&event, &moved);
+            self.emit(event);
+        }
+
+        let mut created: Vec<&PathBuf> = appeared
+            .iter()
+            .filter(|path| !path.parent().is_some_and(|p| appeared.contains(p)))
+            .collect();
+        created.sort();
+        for path in created {
+            if self.is_ignored(path) {
+                continue;
+            }
+            let root = self.settings.name.clone();
+            let original = self
+                .known_directories
+                .get(path)
+                .and_then(|snapshot| self.original_of(path, snapshot, |p| appeared.contains(p)));
+            let event = match original {
+                Some(original) => DirEvent::Copied {
+                    root,
+                    from: original,
+                    to: path.clone(),
+                    offline: true,
+                },
+                None => DirEvent::Created {
+                    root,
+                    path: path.clone(),
+                    placeholder: None,
+                    offline: true,
+                },
+            };
+            self.record(&event, &[]);
+            self.emit(event);
+        }
+        self.prune_lineage();
+        self.dirty = true;
+    }
+
+    /// Records the tracked directories that have no lineage yet as first
+    /// seen where they are, once changes made while no monitor was running
+    /// have been reported.
+    pub fn note_first_sight(&mut self) {
+        let root = self.settings.path.clone();
+        self.record_new(&root, lineage::Change::FirstSeen, None, false);
+    }
+
+    /// Records `event` in the lineage of the directories it concerns.
+    /// `moved` are the directories tracked under the path it left, parents
+    /// first; those now tracked in the same place under the path it arrived
+    /// at take over their ids.
+    fn record(&mut self, event: &DirEvent, moved: &[(PathBuf, DirSnapshot)]) {
+        let Some(change) = lineage::Change::of(event) else {
+            return;
+        };
+        let (from, to) = event.endpoints();
+        let offline = event.is_offline();
+        let at = SystemTime::now();
+        for (old, snapshot) in moved {
+            let Some(id) = &snapshot.id else {
+                continue;
+            };
+            let path = match (from.and_then(|f| old.strip_prefix(f).ok()), to) {
+                (Some(rest), Some(to)) if rest.as_os_str().is_empty() => to.to_path_buf(),
+                (Some(rest), Some(to)) => to.join(rest),
+                _ => old.clone(),
+            };
+            if let Some(current) = self.known_directories.get_mut(&path) {
+                current.id = Some(id.clone());
+            }
+            self.dirty = true;
+            self.lineage.entry(id.clone()).or_default().push(Step {
+                at,
+                change,
+                path,
+                from: None,
+                with_parent: from != Some(old.as_path()),
+                offline,
+            });
+        }
+        // Directories tracked for the first time: the new one itself, or
+        // ones found inside a directory that moved in
+        if let Some(to) = to {
+            let (change, copied_from) = match event {
+                DirEvent::Copied { from, .. } => (change, Some(from.clone())),
+                _ if moved.is_empty() => (change, None),
+                _ => (lineage::Change::FirstSeen, None),
+            };
+            self.record_new(to, change, copied_from, offline);
+        }
+    }
+
+    /// Starts the lineage of the directories tracked at and below `path`
+    /// that have none, with `change`.
+    fn record_new(
+        &mut self,
+        path: &Path,
+        change: lineage::Change,
+        copied_from: Option<PathBuf>,
+        offline: bool,
+    ) {
+        let at = SystemTime::now();
+        let new: Vec<PathBuf> = self
+            .known_directories
+            .entries_under(path)
+            .into_iter()
+            .filter(|(_, snapshot)| {
+                snapshot
+                    .id
+                    .as_ref()
+                    .is_some_and(|id| !self.lineage.contains_key(id))
+            })
+            .map(|(dir, _)| dir)
+            .collect();
+        for dir in new {
+            let Some(id) = self.known_directories.get(&dir).and_then(|s| s.id.clone()) else {
+                continue;
+            };
+            // Directories seen for the first time did not come with anything
+            let top = dir == path || change == lineage::Change::FirstSeen;
+            self.dirty = true;
+            self.lineage.entry(id).or_default().push(Step {
+                at,
+                change,
+                path: dir,
+                from: if top { copied_from.clone() } else { None },
+                with_parent: !top,
+                offline,
+            });
+        }
+    }
+
+    /// Whether `path` is left out by the ignore rules or is part of the
+    /// shadow directory.
+    fn is_ignored(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.settings.path).unwrap_or(path);
+        self.settings.ignore.matches(relative)
+            || self.shadow.as_ref().is_some_and(|s| s.contains(path))
+    }
+
+    fn is_placeholder(&self, path: &Path) -> bool {
+        let relative = path.strip_prefix(&self.settings.path).unwrap_or(path);
+        self.settings.placeholders.matches(relative)
+    }
+
+    /// How many levels below the root `path` is; `None` if it is not under
+    /// the root.
+    fn level(&self, path: &Path) -> Option<usize> {
+        let relative = path.strip_prefix(&self.settings.path).ok()?;
+        Some(
+            relative
+                .components()
+                .filter(|c| matches!(c, Component::Normal(_)))
+                .count(),
+        )
+    }
+
+    /// Whether `path` is a directory that should be tracked: it exists, is
+    /// within the tracked depth, is not ignored and is not inside a
+    /// placeholder.
+    fn is_trackable(&self, path: &Path) -> bool {
+        let within_depth = self
+            .level(path)
+            .is_some_and(|level| level > 0 && self.settings.depth.includes(level));
+        within_depth
+            && path.is_dir()
+            && !self.is_ignored(path)
+            && !path
+                .ancestors()
+                .skip(1)
+                .any(|a| self.placeholders.contains_key(a))
+    }
+
+    fn relative_to_root(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.absolute_root) {
+            Ok(rest) if path.is_absolute() => self.settings.path.join(rest),
+            _ => path.to_path_buf(),
+        }
+    }
+
+    pub fn handle(&mut self, event: notify::Result<Event>) {
+        match event {
+            Ok(event) => {
+                let paths: Vec<PathBuf> = event
+                    .paths
+                    .iter()
+                    .map(|p| self.relative_to_root(p))
+                    .collect();
+                if let Some(shadow) = &self.shadow {
+                    if paths.iter().all(|p| shadow.contains(p)) {
+                        return;
+                    }
+                }
+                // A directory renamed away is followed by its rename, not by
+                // re-reading whatever is at its path by the time it settles
+                let renamed = matches!(event.kind, EventKind::Modify(ModifyKind::Name(_)));
+                match (event.kind, event.tracker()) {
+                    (EventKind::Modify(ModifyKind::Name(RenameMode::Both)), tracker)
+                        if paths.len() == 2 =>
+                    {
+                        if let Some(tracker) = tracker {
+                            self.pending_renames.take(tracker);
+                        }
+                        self.index.rename_tree(&paths[0], &paths[1]);
+                        self.shadow_rename(&paths[0], &paths[1]);
+                        self.rename_copies(&paths[0], &paths[1]);
+                        // Earlier changes may be what the rename refers to
+                        self.settle(true);
+                        self.handle_renamed(&paths[0], &paths[1]);
+                    }
+                    (EventKind::Modify(ModifyKind::Name(RenameMode::From)), Some(tracker)) => {
+                        for path in &paths {
+                            self.pending_renames.insert(tracker, path.clone());
+                        }
+                    }
+                    // The `Both` event that follows carries the source as well
+                    (EventKind::Modify(ModifyKind::Name(RenameMode::To)), Some(tracker))
+                        if self.pending_renames.contains(tracker) => {}
+                    // Unpaired halves are handled like their poll equivalents
+                    (EventKind::Create(_), _)
+                    | (EventKind::Modify(ModifyKind::Name(RenameMode::To)), _) => {
+                        for path in &paths {
+                            self.arrived(path);
+                        }
+                    }
+                    (EventKind::Remove(_), _)
+                    | (EventKind::Modify(ModifyKind::Name(RenameMode::From)), _) => {
+                        for path in &paths {
+                            self.departed(path);
+                        }
+                    }
+                    // Some platforms report each path of a rename on its own,
+                    // without saying which half it is
+                    (EventKind::Modify(ModifyKind::Name(_)), _) => {
+                        for path in &paths {
+                            if path.exists() {
+                                self.arrived(path);
+                            } else {
+                                self.departed(path);
+                            }
+                        }
+                    }
+                    // Files replaced in place keep their link in the mirror;
+                    // others need a new one
+                    (EventKind::Modify(ModifyKind::Data(DataChange::Any)), _)
+                    | (EventKind::Modify(ModifyKind::Metadata(MetadataKind::WriteTime)), _)
+                    | (EventKind::Modify(ModifyKind::Any), _) => {
+                        for path in paths.iter().filter(|p| p.is_file()) {
+                            self.shadow_sync(path);
+                        }
+                    }
+                    _ => {}
+                }
+                // Keep snapshots current as the contents of tracked
+                // directories change
+                for path in &paths {
+                    self.queue_refresh(path, !renamed);
+                    self.mark_stale(path);
+                }
+            }
+            Err(error) => self.emit_error(error.to_string()),
+        }
+    }
+
+    /// Queues the handling of a path that appeared.
+    fn arrived(&mut self, path: &Path) {
+        self.index.add_tree(path);
+        self.shadow_sync(path);
+        self.arrivals.insert(path.to_path_buf(), Instant::now());
+        // A placeholder is recorded at once, so that it is still known if it
+        // is renamed before it settles
+        if self.is_placeholder(path)
+            && self.is_trackable(path)
+            && !self.known_directories.contains(path)
+        {
+            self.placeholders
+                .insert(path.to_path_buf(), DirSnapshot::take(path));
+        }
+        let change = Change::Created(path.to_path_buf());
+        self.settling.push_back((change, Instant::now()));
+    }
+
+    /// Queues the handling of a path that vanished.
+    fn departed(&mut self, path: &Path) {
+        // Polling reports a replacement's creation first
+        if !path.is_dir() {
+            self.index.remove_tree(path);
+            self.copies.retain(|copy, _| !copy.starts_with(path));
+        }
+        if let Some(shadow) = &mut self.shadow {
+            shadow.forget(path);
+        }
+        self.vanishing
+            .push_back((path.to_path_buf(), Instant::now()));
+    }
+
+    /// Handles creations and removals that have settled, and rename sources
+    /// whose destination never arrived, which means they left the watched
+    /// tree.
+    pub fn expire_pending(&mut self) {
+        self.settle(false);
+        self.arrivals
+            .retain(|_, seen| seen.elapsed() < ARRIVAL_WINDOW);
+        for (path, seen) in self.pending_renames.take_expired() {
+            self.index.remove_tree(&path);
+            if let Some(shadow) = &mut self.shadow {
+                shadow.forget(&path);
+            }
+            self.handle_removed(&path, seen);
+        }
+        if let Some(shadow) = &mut self.shadow {
+            let result = shadow.expire();
+            self.report_shadow(result);
+        }
+        self.retake_subdirs();
+        if self.lineage_checked.elapsed() >= LINEAGE_CHECK_INTERVAL {
+            self.prune_lineage();
+        }
+    }
+
+    /// Drops the lineage of directories no longer tracked whose last change
+    /// is older than [`lineage::RETENTION`].
+    fn prune_lineage(&mut self) {
+        self.lineage_checked = Instant::now();
+        let tracked: HashSet<String> = self
+            .known_directories
+            .entries()
+            .into_iter()
+            .filter_map(|(_, snapshot)| snapshot.id.clone())
+            .collect();
+        let before = self.lineage.len();
+        self.lineage.retain(|id, steps| {
+            tracked.contains(id)
+                || steps.last().is_some_and(|step| {
+                    step.at
+                        .elapsed()
+                        .map_or(true, |age| age < lineage::RETENTION)
+                })
+        });
+        self.dirty |= self.lineage.len() != before;
+    }
+
+    /// Adds up the manifest of the directory at the top of `dirs`, which are
+    /// it and the tracked directories below it, parents first.
+    fn manifest(&self, dirs: &[(PathBuf, DirSnapshot)]) -> Option<Manifest> {
+        Manifest::add_up(dirs, self.settings.manifest_entries, |p| self.is_ignored(p))
+    }
+
+    /// Whether the files inside `path` are counted in the snapshot of its
+    /// parent, as it is not tracked itself: it is too deep or a placeholder.
+    fn is_counted_in_parent(&self, path: &Path) -> bool {
+        let too_deep = self
+            .level(path)
+            .is_some_and(|level| !self.settings.depth.includes(level));
+        (too_deep || self.is_placeholder(path)) && !self.is_ignored(path)
+    }
+
+    /// Counts the files in the subdirectories of `path` that are counted in
+    /// its snapshot, reusing the totals in `known` for those it has.
+    fn subdir_totals(
+        &self,
+        path: &Path,
+        known: &BTreeMap<String, Totals>,
+    ) -> BTreeMap<String, Totals> {
+        let mut subdirs = BTreeMap::new();
+        let Ok(entries) = std::fs::read_dir(path) else {
+            return subdirs;
+        };
+        for entry in entries.flatten() {
+            let dir = entry.path();
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) || !self.is_counted_in_parent(&dir) {
+                continue;
+            }
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let totals = match known.get(&name) {
+                Some(totals) => *totals,
+                None => Totals::take(&dir, |p| self.is_ignored(p)),
+            };
+            subdirs.insert(name, totals);
+        }
+        subdirs
+    }
+
+    /// Notes that the subdirectory `path` is in needs counting again, if it
+    /// is one counted in its tracked parent. Changes directly inside a
+    /// tracked directory are picked up when it is re-read.
+    fn mark_stale(&mut self, path: &Path) {
+        let Some(dir) = path
+            .ancestors()
+            .skip(1)
+            .find(|a| self.known_directories.contains(a))
+        else {
+            return;
+        };
+        let Some(Component::Normal(name)) = path
+            .strip_prefix(dir)
+            .ok()
+            .and_then(|rest| rest.components().next())
+        else {
+            return;
+        };
+        let subdir = dir.join(name);
+        if subdir != path && self.is_counted_in_parent(&subdir) {
+            self.stale_subdirs.insert(subdir, Instant::now());
+        }
+    }
+
+    /// Counts again the files in the subdirectories whose contents have
+    /// stopped changing. Ones that have gone are dropped when their parent
+    /// is re-read.
+    fn retake_subdirs(&mut self) {
+        let quiet: Vec<PathBuf> = self
+            .stale_subdirs
+            .iter()
+            .filter(|(_, changed)| changed.elapsed() >= MANIFEST_QUIET)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in quiet {
+            self.stale_subdirs.remove(&path);
+            let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+                continue;
+            };
+            if !path.is_dir() || !self.known_directories.contains(parent) {
+                continue;
+            }
+            let totals = Totals::take(&path, |p| self.is_ignored(p));
+            let name = name.to_string_lossy().into_owned();
+            if let Some(snapshot) = self.known_directories.get_mut(parent) {
+                if snapshot.subdirs.get(&name) != Some(&totals) {
+                    snapshot.subdirs.insert(name, totals);
+                    self.dirty = true;
+                }
+            }
+        }
+    }
+
+    /// Links `path` and whatever is inside it into the shadow mirror.
+    fn shadow_sync(&mut self, path: &Path) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+        let result = shadow.sync(path, |p| self.is_ignored(p));
+        self.report_shadow(result);
+    }
+
+    fn shadow_rename(&mut self, from: &Path, to: &Path) {
+        let Some(shadow) = &self.shadow else {
+            return;
+        };
+        let result = shadow.rename(from, to, |p| self.is_ignored(p));
+        self.report_shadow(result);
+    }
+
+    /// Keeps the mirror of the removed directory `path` as a snapshot and
+    /// returns its id.
+    fn keep_shadow(&mut self, path: &Path) -> Option<String> {
+        let shadow = self.shadow.as_ref()?;
+        let original = self.absolute(path);
+        match shadow.keep(path, &original, &self.settings.name) {
+            Ok(id) => id,
+            Err(e) => {
+                self.emit_error(format!("cannot keep {}: {}", path.display(), e));
+                None
+            }
+        }
+    }
+
+    fn report_shadow(&mut self, result: std::io::Result<()>) {
+        if let Err(e) = result {
+            self.emit_error(format!("shadow mirror: {}", e));
+        }
+    }
+
+    /// Drops files deleted while no monitor was running from the shadow
+    /// mirror, once offline removals have been kept.
+    pub fn prune_shadow(&mut self) {
+        if let Some(shadow) = &self.shadow {
+            let result = shadow.prune_stale();
+            self.report_shadow(result);
+        }
+    }
+
+    /// Handles the creations and content changes seen at least the settle
+    /// delay ago, and the removals seen at least the rename window ago; with
+    /// `flush`, everything waiting.
+    fn settle(&mut self, flush: bool) {
+        while let Some((_, seen)) = self.settling.front() {
+            if !flush && seen.elapsed() < SETTLE_DELAY {
+                break;
+            }
+            match self.settling.pop_front() {
+                Some((Change::Created(path), _)) => {
+                    self.note_copy(&path);
+                    self.handle_created(&path)
+                }
+                Some((Change::Modified(path), _)) => self.refresh(&path),
+                None => break,
+            }
+        }
+        let window = self.settings.rename_window.max(SETTLE_DELAY);
+        while let Some((_, seen)) = self.vanishing.front() {
+            if !flush && seen.elapsed() < window {
+                break;
+            }
+            if let Some((path, seen)) = self.vanishing.pop_front() {
+                self.handle_removed(&path, seen);
+            }
+        }
+    }
+
+    /// Records `path` as a copy if it is a new directory with nearly the
+    /// contents or the name of a tracked one that is still there, so it is
+    /// never taken for where that one went. Directories that arrived along
+    /// with their parent are part of its tree and not compared on their own.
+    fn note_copy(&mut self, path: &Path) {
+        let with_parent = match (
+            path.parent().map(|p| self.arrivals.get(p)),
+            self.arrivals.get(path),
+        ) {
+            (Some(Some(parent)), Some(seen)) => seen.duration_since(*parent) < SETTLE_DELAY,
+            _ => false,
+        };
+        if with_parent || self.known_directories.contains(path) || !path.is_dir() {
+            return;
+        }
+        let snapshot = DirSnapshot::take(path);
+        let original = self
+            .original_of(path, &snapshot, |_| false)
+            .or_else(|| self.namesake_of(path));
+        if let Some(original) = original {
+            self.copies.insert(path.to_path_buf(), original);
+        }
+    }
+
+    /// A tracked directory elsewhere with the same name as `path`.
+    fn namesake_of(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?;
+        self.known_directories
+            .entries()
+            .into_iter()
+            .map(|(original, _)| original)
+            .find(|original| {
+                original.file_name() == Some(name)
+                    && !original.starts_with(path)
+                    && !path.starts_with(original)
+                    && original.is_dir()
+            })
+    }
+
+    /// The tracked directory, other than those `exclude` holds for, that
+    /// the directory at `path` is a copy of.
+    fn original_of(
+        &self,
+        path: &Path,
+        snapshot: &DirSnapshot,
+        exclude: impl Fn(&Path) -> bool,
+    ) -> Option<PathBuf> {
+        self.known_directories
+            .entries()
+            .into_iter()
+            .filter(|(original, old)| {
+                original != path
+                    && !exclude(original)
+                    && same_contents(old, snapshot)
+                    && original.is_dir()
+            })
+            .max_by(|(_, a), (_, b)| {
+                let a = a.fingerprint.similarity(&snapshot.fingerprint);
+                let b = b.fingerprint.similarity(&snapshot.fingerprint);
+                a.partial_cmp(&b).unwrap_or(Ordering::Equal)
+            })
+            .map(|(original, _)| original)
+    }
+
+    /// Moves the copies recorded under `from` to `to`.
+    fn rename_copies(&mut self, from: &Path, to: &Path) {
+        let moved: Vec<PathBuf> = self
+            .copies
+            .keys()
+            .filter(|copy| copy.starts_with(from))
+            .cloned()
+            .collect();
+        for copy in moved {
+            if let (Some(original), Ok(rest)) = (self.copies.remove(&copy), copy.strip_prefix(from))
+            {
+                self.copies.insert(to.join(rest), original);
+            }
+        }
+    }
+
+    /// Queues a re-read of the tracked directory containing `path`, and with
+    /// `itself` of `path` too if it is tracked, as another directory may
+    /// have taken its place.
+    fn queue_refresh(&mut self, path: &Path, itself: bool) {
+        let itself = itself.then_some(path);
+        for dir in [path.parent(), itself].into_iter().flatten() {
+            if !self.known_directories.contains(dir) {
+                continue;
+            }
+            if let Some((Change::Modified(last), _)) = self.settling.back() {
+                if last == dir {
+                    continue;
+                }
+            }
+            let change = Change::Modified(dir.to_path_buf());
+            self.settling.push_back((change, Instant::now()));
+        }
+    }
+
+    /// Re-reads a tracked directory whose immediate contents changed.
+    fn refresh(&mut self, path: &Path) {
+        if path.is_dir() {
+            let Some(old) = self.known_directories.get(path) else {
+                return;
+            };
+            let mut snapshot = DirSnapshot::take(path);
+            if old.identity.is_some()
+                && snapshot.identity.is_some()
+                && snapshot.identity != old.identity
+            {
+                return self.handle_replaced(path);
+            }
+            snapshot.id.clone_from(&old.id);
+            snapshot.subdirs = self.subdir_totals(path, &old.subdirs);
+            if &snapshot != old {
+                self.known_directories.update(path, snapshot);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Tracks `path` and the directories below it.
+    fn track(&mut self, path: &Path, mut snapshot: DirSnapshot) {
+        snapshot.id.get_or_insert_with(lineage::new_id);
+        snapshot.subdirs = self.subdir_totals(path, &BTreeMap::new());
+        self.known_directories.insert(path, snapshot);
+        self.dirty = true;
+        self.track_below(path);
+    }
+
+    /// Tracks the directories below `path` down to the configured depth,
+    /// without reporting them.
+    fn track_below(&mut self, path: &Path) {
+        let Some(level) = self.level(path) else {
+            return;
+        };
+        let mut walk = WalkDir::new(path).min_depth(1);
+        if let Some(max) = self.settings.depth.max_level() {
+            if max <= level {
+                return;
+            }
+            walk = walk.max_depth(max - level);
+        }
+
+        let mut entries = walk.into_iter();
+        while let Some(entry) = entries.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let dir = entry.path();
+            if !dir.is_dir() {
+                continue;
+            }
+            if self.is_ignored(dir) {
+                entries.skip_current_dir();
+                continue;
+            }
+            let mut snapshot = DirSnapshot::take(dir);
+            if self.is_placeholder(dir) {
+                self.placeholders.insert(dir.to_path_buf(), snapshot);
+                entries.skip_current_dir();
+            } else {
+                snapshot.id = Some(lineage::new_id());
+                snapshot.subdirs = self.subdir_totals(dir, &BTreeMap::new());
+                self.known_directories.insert(dir, snapshot);
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Tracks `path` after a move, if it landed somewhere tracked.
+    fn track_if_trackable(&mut self, path: &Path) {
+        if self.is_trackable(path) {
+            self.track(path, DirSnapshot::take(path));
+        }
+    }
+
+    /// Whether the tracked `path` now holds another directory than the one
+    /// recorded there.
+    fn is_replaced(&self, path: &Path) -> bool {
+        self.known_directories.get(path).is_some_and(|old| {
+            old.identity.is_some()
+                && DirIdentity::of(path).is_some_and(|now| Some(now) != old.identity)
+        })
+    }
+
+    /// Handles another directory standing at the tracked `path`: the one
+    /// recorded there left, and the new one arrived. A removal of the path
+    /// still waiting out the rename window is handled with it.
+    fn handle_replaced(&mut self, path: &Path) {
+        let vanished = self
+            .vanishing
+            .iter()
+            .filter(|(p, _)| p == path)
+            .map(|(_, seen)| *seen)
+            .min()
+            .unwrap_or_else(Instant::now);
+        self.vanishing.retain(|(p, _)| !p.starts_with(path));
+        self.index.add_tree(path);
+        // The new one may be a tracked directory that moved here, as when two
+        // swap places, so it is looked up where it is now
+        if let Some(identity) = DirIdentity::of(path) {
+            if let Some(other) = self.known_directories.find_identity(&identity) {
+                let other = other.to_path_buf();
+                self.index.add_tree(&other);
+            }
+        }
+        self.handle_removed(path, vanished);
+        self.handle_created(path);
+    }
+
+    /// Handles the directory tracked at `path` leaving it, when another one
+    /// has just been found there.
+    fn make_way(&mut self, path: &Path) {
+        if self.is_replaced(path) {
+            self.handle_removed(path, Instant::now());
+        }
+    }
+
+    /// Stops tracking `path`, the directories below it and any placeholders
+    /// inside it, returning what was recorded for them, `path` first.
+    fn untrack(&mut self, path: &Path) -> Vec<(PathBuf, DirSnapshot)> {
+        self.placeholders.retain(|p, _| !p.starts_with(path));
+        let removed = self.known_directories.remove(path);
+        self.dirty |= !removed.is_empty();
+        removed
+    }
+
+    fn handle_created(&mut self, path: &Path) {
+        if self.is_replaced(path) {
+            return self.handle_replaced(path);
+        }
+        if !self.is_trackable(path)
+            || self.known_directories.contains(path)
+            || self.placeholders.contains_key(path)
+        {
+            return;
+        }
+        // A directory created along with its parent is reported as part of
+        // the parent
+        if let Some(parent) = path.parent() {
+            if !self.known_directories.contains(parent) && self.is_trackable(parent) {
+                return self.handle_created(parent);
+            }
+        }
+        let snapshot = DirSnapshot::take(path);
+        if self.is_placeholder(path) {
+            self.placeholders.insert(path.to_path_buf(), snapshot);
+            return;
+        }
+        // A placeholder renamed within one poll cycle shows up as a new
+        // directory with the placeholder's identity
+        if let Some(placeholder) = self.claim_placeholder(&snapshot) {
+            let event = DirEvent::Created {
+                root: self.settings.name.clone(),
+                path: path.to_path_buf(),
+                placeholder: Some(placeholder),
+                offline: false,
+            };
+            self.track(path, snapshot);
+            self.record(&event, &[]);
+            self.emit(event);
+            return;
+        }
+        // A known directory showing up under a new name is reported once its
+        // old path is removed
+        if let Some(identity) = &snapshot.identity {
+            if self.known_directories.find_identity(identity).is_some() {
+                return;
+            }
+        }
+        // So is one that looks like a tracked directory whose removal is
+        // still settling
+        if self.matches_pending_removal(&snapshot) {
+            return;
+        }
+
+        // Only a copy of the contents is logged as one; a namesake may just
+        // share a common name. Copies of a removed directory were compared
+        // with it when it went.
+        let original = self.copies.get(path).filter(|original| {
+            self.known_directories
+                .get(original)
+                .is_none_or(|old| same_contents(old, &snapshot))
+        });
+        let event = match original {
+            Some(original) => DirEvent::Copied {
+                root: self.settings.name.clone(),
+                from: original.clone(),
+                to: path.to_path_buf(),
+                offline: false,
+            },
+            None => DirEvent::Created {
+                root: self.settings.name.clone(),
+                path: path.to_path_buf(),
+                placeholder: None,
+                offline: false,
+            },
+        };
+        self.track(path, snapshot);
+        self.record(&event, &[]);
+        self.emit(event);
+    }
+
+    /// Whether a removal still waiting to be handled is of a tracked
+    /// directory with nearly the contents of `snapshot`.
+    fn matches_pending_removal(&self, snapshot: &DirSnapshot) -> bool {
+        self.vanishing.iter().any(|(path, _)| {
+            !path.exists()
+                && self
+                    .known_directories
+                    .get(path)
+                    .is_some_and(|old| same_contents(old, snapshot))
+        })
+    }
+
+    /// Removes and returns the placeholder with the identity of `snapshot`.
+    fn claim_placeholder(&mut self, snapshot: &DirSnapshot) -> Option<PathBuf> {
+        snapshot.identity?;
+        let path = self
+            .placeholders
+            .iter()
+            .find(|(_, placeholder)| placeholder.identity == snapshot.identity)
+            .map(|(path, _)| path.clone())?;
+        self.placeholders.remove(&path);
+        Some(path)
+    }
+
+    /// Handles a placeholder renamed to `to`.
+    fn handle_placeholder_renamed(&mut self, from: &Path, to: &Path) {
+        if !self.is_trackable(to) || self.known_directories.contains(to) {
+            return;
+        }
+        let snapshot = DirSnapshot::take(to);
+        if self.is_placeholder(to) {
+            self.placeholders.insert(to.to_path_buf(), snapshot);
+        } else {
+            let event = DirEvent::Created {
+                root: self.settings.name.clone(),
+                path: to.to_path_buf(),
+                placeholder: Some(from.to_path_buf()),
+                offline: false,
+            };
+            self.track(to, snapshot);
+            self.record(&event, &[]);
+            self.emit(event);
+        }
+    }
+
+    /// Handles a placeholder whose path vanished. If it was renamed, the new
+    /// name is logged as the directory's creation; if it was deleted or moved
+    /// away, nothing is.
+    fn handle_placeholder_removed(&mut self, path: &Path) {
+        let Some(snapshot) = self.placeholders.remove(path) else {
+            return;
+        };
+        if snapshot.identity.is_none() {
+            return;
+        }
+        let Some(Ok(entries)) = path.parent().map(std::fs::read_dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let renamed = entry.path();
+            if self.known_directories.contains(&renamed) || self.placeholders.contains_key(&renamed)
+            {
+                continue;
+            }
+            if DirSnapshot::take(&renamed).identity == snapshot.identity {
+                self.placeholders.insert(path.to_path_buf(), snapshot);
+                return self.handle_created(&renamed);
+            }
+        }
+    }
+
+    /// Handles a rename reported with both paths, so no search is needed.
+    fn handle_renamed(&mut self, from: &Path, to: &Path) {
+        // A placeholder renamed before its arrival was handled was never
+        // there to be recorded
+        let unseen_placeholder = self.is_placeholder(from)
+            && self.arrivals.contains_key(from)
+            && !self.known_directories.contains(from);
+        if self.placeholders.remove(from).is_some() || unseen_placeholder {
+            return self.handle_placeholder_renamed(from, to);
+        }
+        let moved = self.untrack(from);
+        if moved.is_empty() {
+            return self.handle_created(to);
+        }
+        let event = self
+            .trashed_event(from, to, false)
+            .unwrap_or_else(|| DirEvent::moved(&self.settings.name, from, to, None, false))
+            .with_manifest(self.manifest(&moved));
+        self.make_way(to);
+        if to.is_dir() {
+            self.track_if_trackable(to);
+        } else {
+            self.track_moved(from, to, &moved);
+        }
+        self.record(&event, &moved);
+        self.emit(event);
+    }
+
+    /// Tracks the directories `moved` from `from` under `to` as they were,
+    /// as `to` has been renamed again already and that rename is still to
+    /// be handled.
+    fn track_moved(&mut self, from: &Path, to: &Path, moved: &[(PathBuf, DirSnapshot)]) {
+        for (path, snapshot) in moved {
+            let Ok(rest) = path.strip_prefix(from) else {
+                continue;
+            };
+            let path = to.join(rest);
+            let within_depth = self
+                .level(&path)
+                .is_some_and(|level| level > 0 && self.settings.depth.includes(level));
+            if within_depth && !self.is_ignored(&path) {
+                self.known_directories.insert(&path, snapshot.clone());
+                self.dirty = true;
+            }
+        }
+    }
+
+    /// Looks for the directory that vanished from `path`, first inside the
+    /// root and then in the search roots, and returns the event for where it
+    /// went, if it was found.
+    fn locate(
+        &self,
+        path: &Path,
+        snapshot: &DirSnapshot,
+        departure: &Departure,
+        is_known: impl Fn(&Path) -> bool,
+    ) -> (MoveResolution, Option<DirEvent>) {
+        let name = &self.settings.name;
+        let Departure {
+            arrivals,
+            since,
+            last_seen,
+            offline,
+        } = *departure;
+        if self.settings.detect_trash {
+            let trashes = trash_directories(&self.absolute_root, &self.settings.search_roots);
+            let original = self.absolute(path);
+            let same_identity = |p: &Path| {
+                let identity = snapshot.identity?;
+                Some(DirSnapshot::take(p).identity == Some(identity))
+            };
+            if let Some(info) = find_trashed(&original, &trashes, same_identity, last_seen) {
+                let event = self.trashed_event(path, &info.trashed, offline);
+                return (MoveResolution::NotFound, event);
+            }
+        }
+        let mut resolution = resolve_move(
+            path,
+            snapshot,
+            &self.index,
+            &self.settings.search_path,
+            &is_known,
+            |p| since.iter().any(|new| p.starts_with(new)),
+        );
+        if matches!(resolution, MoveResolution::NotFound) {
+            let arrivals = arrivals
+                .iter()
+                .filter(|p| {
+                    p.as_path() != path
+                        && p.starts_with(&self.settings.search_path)
+                        && !is_known(p)
+                        && p.is_dir()
+                })
+                .cloned()
+                .collect();
+            resolution = resolve_by_fingerprint(snapshot, arrivals);
+        }
+        if !matches!(resolution, MoveResolution::NotFound) {
+            let event = match resolution.new_path() {
+                Some(to) => self.trashed_event(path, to, offline),
+                None => None,
+            }
+            .or_else(|| resolution.event(name, path, offline));
+            return (resolution, event);
+        }
+        // Search roots inside the root were covered above
+        let outside: Vec<PathBuf> = self
+            .settings
+            .search_roots
+            .iter()
+            .filter(|p| !p.starts_with(&self.settings.path) && !p.starts_with(&self.absolute_root))
+            .cloned()
+            .collect();
+        let resolution = resolve_outside(path, snapshot, &outside);
+        let event = match resolution.new_path() {
+            Some(to) => self.trashed_event(path, to, offline),
+            None => None,
+        }
+        .or_else(|| resolution.moved_out_event(name, path, offline));
+        (resolution, event)
+    }
+
+    /// The event for `path` having gone to `to`, if that is an item in a
+    /// trash and trash detection is on.
+    fn trashed_event(&self, path: &Path, to: &Path, offline: bool) -> Option<DirEvent> {
+        if !self.settings.detect_trash {
+            return None;
+        }
+        let info = TrashInfo::for_trashed(to)?;
+        Some(DirEvent::Trashed {
+            root: self.settings.name.clone(),
+            path: path.to_path_buf(),
+            trashed_to: info.trashed,
+            deleted_at: info.deleted_at,
+            manifest: None,
+            offline,
+        })
+    }
+
+    /// `path`, given under the root as configured, as an absolute path.
+    fn absolute(&self, path: &Path) -> PathBuf {
+        match path.strip_prefix(&self.settings.path) {
+            Ok(rest) => self.absolute_root.join(rest),
+            Err(_) => path.to_path_buf(),
+        }
+    }
+
+    /// Handles the directory that vanished from `path` at `vanished`.
+    fn handle_removed(&mut self, path: &Path, vanished: Instant) {
+        if self.placeholders.contains_key(path) {
+            return self.handle_placeholder_removed(path);
+        }
+        // When a whole tree goes, only its top is reported
+        let mut path = path.to_path_buf();
+        while let Some(parent) = path.parent() {
+            if !self.known_directories.contains(parent) || parent.exists() {
+                break;
+            }
+            path = parent.to_path_buf();
+        }
+        // Still there, as after a replacement that has been handled already
+        let identity = DirIdentity::of(&path);
+        if identity.is_some()
+            && self
+                .known_directories
+                .get(&path)
+                .is_some_and(|old| old.identity == identity)
+        {
+            return;
+        }
+        let moved = self.untrack(&path);
+        let Some((_, snapshot)) = moved.first() else {
+            return;
+        };
+
+        // Directories that appeared while this one was still there are
+        // copies of it if they hold the same files, now that copying is
+        // likely done. A poll scan reports creations before removals, so a
+        // copy made just before the original was deleted counts too.
+        let copies: Vec<PathBuf> = self
+            .arrivals
+            .iter()
+            .filter(|(arrival, seen)| {
+                **seen < vanished
+                    && **arrival != path
+                    && !self.copies.contains_key(*arrival)
+                    && arrival.is_dir()
+                    && same_contents(snapshot, &DirSnapshot::take(arrival))
+            })
+            .map(|(arrival, _)| arrival.clone())
+            .collect();
+        for copy in &copies {
+            self.copies.insert(copy.clone(), path.clone());
+        }
+
+        let arrivals: Vec<PathBuf> = self.arrivals.keys().cloned().collect();
+        let since: Vec<PathBuf> = self
+            .arrivals
+            .iter()
+            .filter(|(_, seen)| **seen >= vanished)
+            .map(|(p, _)| p.clone())
+            .collect();
+        // A poll scan can be a whole interval after the directory went
+        let last_seen = SystemTime::now()
+            .checked_sub(vanished.elapsed() + self.settings.poll_interval)
+            .unwrap_or(UNIX_EPOCH);
+        let departure = Departure {
+            arrivals: &arrivals,
+            since: &since,
+            last_seen,
+            offline: false,
+        };
+        let (resolution, event) = self.locate(&path, snapshot, &departure, |p| {
+            self.known_directories.contains(p) || self.copies.contains_key(p)
+        });
+        let event = match event {
+            Some(event) => event,
+            None => DirEvent::Removed {
+                root: self.settings.name.clone(),
+                path: path.clone(),
+                snapshot: self.keep_shadow(&path),
+                manifest: None,
+                offline: false,
+            },
+        };
+        let event = event.with_manifest(self.manifest(&moved));
+        if let Some(new_path) = resolution.new_path() {
+            self.make_way(new_path);
+            self.track_if_trackable(new_path);
+        }
+        self.record(&event, &moved);
+        self.emit(event);
+        // Copies held back in case they were this directory under a new name
+        for copy in &copies {
+            self.handle_created(copy);
+        }
+        // An ambiguous move has no single directory to track in its place;
+        // candidates held back as its possible new path are new in their own
+        // right
+        if let MoveResolution::Ambiguous(candidates) = &resolution {
+            for candidate in candidates {
+                self.handle_created(&candidate.path);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{backend::Backend, filter::Filter, shadow};
+    use std::{
+        fs,
+        sync::mpsc::{self, Receiver},
+    };
+
+    const BACKENDS: [Backend; 2] = [Backend::Native, Backend::Poll];
+
+    /// A root in a directory of its own, with its monitor fed by the test
+    /// instead of a [`crate::DirMonitor`].
+    struct Watched {
+        root: PathBuf,
+        monitor: RootMonitor,
+        rx: Receiver<TaggedEvent>,
+    }
+
+    impl Watched {
+        /// Starts watching a new root holding `files`, each in its
+        /// directories, removing what a previous run left behind first.
+        fn start(name: &str, backend: Backend, files: &[&str]) -> Watched {
+            let root = std::env::temp_dir().join(format!(
+                "dirmon-root-{}-{}-{}",
+                std::process::id(),
+                name,
+                backend
+            ));
+            let _ = fs::remove_dir_all(&root);
+            fs::create_dir_all(&root).unwrap();
+            for file in files {
+                write(&root.join(file));
+            }
+            let settings = RootSettings {
+                name: "root".to_string(),
+                path: root.clone(),
+                backend,
+                poll_interval: Duration::from_millis(100),
+                depth: Depth::Levels(2),
+                ignore: Filter::default_ignore(),
+                placeholders: Filter::default_placeholders(),
+                search_path: root.clone(),
+                search_roots: Vec::new(),
+                detect_trash: false,
+                shadow: None,
+                shadow_retention: shadow::DEFAULT_RETENTION,
+                manifest_entries: 0,
+                rename_window: Duration::from_millis(500),
+            };
+            let (tx, rx) = mpsc::channel();
+            let mut monitor = RootMonitor::start(0, settings, tx).unwrap();
+            monitor.note_first_sight();
+            monitor.take_events().for_each(drop);
+            Watched { root, monitor, rx }
+        }
+
+        fn path(&self, relative: &str) -> PathBuf {
+            self.root.join(relative)
+        }
+
+        /// Handles what the watcher reports until everything has settled,
+        /// and returns the events produced, described with paths relative
+        /// to the root.
+        fn events(&mut self) -> Vec<String> {
+            let deadline = Instant::now() + Duration::from_millis(1500);
+            while let Some(left) = deadline.checked_duration_since(Instant::now()) {
+                let wait = left.min(Duration::from_millis(50));
+                if let Ok((_, event)) = self.rx.recv_timeout(wait) {
+                    self.monitor.handle(event);
+                }
+                self.monitor.expire_pending();
+            }
+            let events: Vec<DirEvent> = self.monitor.take_events().collect();
+            events.iter().map(|event| self.describe(event)).collect()
+        }
+
+        fn describe(&self, event: &DirEvent) -> String {
+            let relative = |path: &Path| {
+                let path = path.strip_prefix(&self.root).unwrap_or(path);
+                path.display().to_string()
+            };
+            match event {
+                DirEvent::Created {
+                    path,
+                    placeholder: Some(placeholder),
+                    ..
+                } => format!("created {} as {}", relative(placeholder), relative(path)),
+                DirEvent::Created { path, .. } => format!("created {}", relative(path)),
+                DirEvent::Copied { from, to, .. } => {
+                    format!("copied {} to {}", relative(from), relative(to))
+                }
+                DirEvent::Removed { path, .. } => format!("removed {}", relative(path)),
+                DirEvent::Moved { from, to, .. } => {
+                    format!("moved {} to {}", relative(from), relative(to))
+                }
+                DirEvent::Renamed { from, to, .. } => {
+                    format!("renamed {} to {}", relative(from), relative(to))
+                }
+                other => format!("{:?}", other),
+            }
+        }
+    }
+
+    impl Drop for Watched {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.root);
+        }
+    }
+
+    /// Creates the file at `path`, and the directories it is in.
+    fn write(path: &Path) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, path.display().to_string()).unwrap();
+    }
+
+    /// Copies the directory at `from` to `to`, with everything in it.
+    fn copy_dir(from: &Path, to: &Path) {
+        for entry in WalkDir::new(from) {
+            let entry = entry.unwrap();
+            let target = to.join(entry.path().strip_prefix(from).unwrap());
+            if entry.file_type().is_dir() {
+                fs::create_dir_all(&target).unwrap();
+            } else {
+                fs::copy(entry.path(), &target).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn rename_is_reported_once_for_the_top() {
+        for backend in BACKENDS {
+            let mut watched = Watched::start("rename", backend, &["Acme/docs/a.txt"]);
+            fs::rename(watched.path("Acme"), watched.path("Acme Corp")).unwrap();
+            assert_eq!(
+                watched.events(),
+                ["renamed Acme to Acme Corp"],
+                "{}",
+                backend
+            );
+            assert!(watched
+                .monitor
+                .known_directories
+                .contains(&watched.path("Acme Corp/docs")));
+            assert!(!watched
+                .monitor
+                .known_directories
+                .contains(&watched.path("Acme/docs")));
+        }
+    }
+
+    #[test]
+    fn move_to_another_parent_is_a_move() {
+        for backend in BACKENDS {
+            let mut watched = Watched::start("move", backend, &["Acme/a.txt", "Clients/c.txt"]);
+            fs::rename(watched.path("Acme"), watched.path("Clients/Acme")).unwrap();
+            assert_eq!(
+                watched.events(),
+                ["moved Acme to Clients/Acme"],
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn copy_is_reported_with_its_original() {
+        for backend in BACKENDS {
+            let files = ["Acme/a.txt", "Acme/b.txt", "Acme/docs/c.txt"];
+            let mut watched = Watched::start("copy", backend, &files);
+            copy_dir(&watched.path("Acme"), &watched.path("Acme copy"));
+            assert_eq!(
+                watched.events(),
+                ["copied Acme to Acme copy"],
+                "{}",
+                backend
+            );
+            assert!(watched
+                .monitor
+                .known_directories
+                .contains(&watched.path("Acme")));
+        }
+    }
+
+    #[test]
+    fn copy_is_not_where_the_deleted_original_went() {
+        for backend in BACKENDS {
+            let files = ["Acme/a.txt", "Acme/b.txt"];
+            let mut watched = Watched::start("copy-delete", backend, &files);
+            copy_dir(&watched.path("Acme"), &watched.path("Backup/Acme"));
+            assert_eq!(watched.events(), ["created Backup"], "{}", backend);
+            fs::remove_dir_all(watched.path("Acme")).unwrap();
+            assert_eq!(watched.events(), ["removed Acme"], "{}", backend);
+        }
+    }
+
+    #[test]
+    fn directory_replaced_at_the_same_path_is_removed_and_created() {
+        for backend in BACKENDS {
+            let mut watched = Watched::start("replace", backend, &["Acme/old.txt"]);
+            fs::remove_dir_all(watched.path("Acme")).unwrap();
+            write(&watched.path("Acme/new.txt"));
+            assert_eq!(
+                watched.events(),
+                ["removed Acme", "created Acme"],
+                "{}",
+                backend
+            );
+            let tracked = watched.monitor.known_directories.get(&watched.path("Acme"));
+            assert_eq!(
+                tracked.and_then(|snapshot| snapshot.identity),
+                DirIdentity::of(&watched.path("Acme"))
+            );
+        }
+    }
+
+    #[test]
+    fn swapped_directories_are_tracked_by_identity() {
+        for backend in BACKENDS {
+            let mut watched = Watched::start("swap", backend, &["X/x.txt", "Y/y.txt"]);
+            let x = DirIdentity::of(&watched.path("X"));
+            let y = DirIdentity::of(&watched.path("Y"));
+            fs::rename(watched.path("X"), watched.path("T")).unwrap();
+            fs::rename(watched.path("Y"), watched.path("X")).unwrap();
+            fs::rename(watched.path("T"), watched.path("Y")).unwrap();
+
+            // Polling only sees where each one ended up
+            let mut events = watched.events();
+            events.sort();
+            let expected: &[&str] = match backend {
+                Backend::Poll => &["renamed X to Y", "renamed Y to X"],
+                _ => &["renamed T to Y", "renamed X to T", "renamed Y to X"],
+            };
+            assert_eq!(events, expected, "{}", backend);
+            let known = &watched.monitor.known_directories;
+            let identity = |name| known.get(&watched.path(name)).and_then(|s| s.identity);
+            assert_eq!(identity("X"), y, "{}", backend);
+            assert_eq!(identity("Y"), x, "{}", backend);
+            assert!(!known.contains(&watched.path("T")), "{}", backend);
+        }
+    }
+
+    #[test]
+    fn placeholder_is_created_under_its_final_name() {
+        for backend in BACKENDS {
+            let mut watched = Watched::start("placeholder", backend, &[]);
+            fs::create_dir(watched.path("New folder")).unwrap();
+            assert!(watched.events().is_empty(), "{}", backend);
+            fs::rename(watched.path("New folder"), watched.path("Acme")).unwrap();
+            assert_eq!(
+                watched.events(),
+                ["created New folder as Acme"],
+                "{}",
+                backend
+            );
+        }
+    }
+
+    #[test]
+    fn placeholder_renamed_before_it_settles_is_created_as() {
+        let mut watched = Watched::start("placeholder-quick", Backend::Native, &[]);
+        fs::create_dir(watched.path("New folder")).unwrap();
+        fs::rename(watched.path("New folder"), watched.path("Acme")).unwrap();
+        assert_eq!(watched.events(), ["created New folder as Acme"]);
+    }
+}
@@ -0,0 +1,425 @@
+//! A hardlinked mirror of a root, so removed folders can be recovered.
+//!
+//! The mirror lives in `<shadow dir>/mirror/<absolute root path>` and holds
+//! a hard link to every file under the root, which keeps a file's data
+//! alive after the original is deleted. When a tracked directory is
+//! removed, its part of the mirror is moved to `<shadow dir>/removed/<id>`
+//! as a snapshot, next to a `snapshot.json` describing it. Snapshots older
+//! than the retention period are deleted.
+
+use crate::error::Error;
+use chrono::{DateTime, FixedOffset, SecondsFormat, Utc};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fs, io,
+    path::{Component, Path, PathBuf},
+    time::{Duration, Instant, SystemTime},
+};
+use walkdir::WalkDir;
+
+/// How long snapshots are kept unless configured otherwise.
+pub const DEFAULT_RETENTION: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+
+/// How long a deleted file stays in the mirror while the directory it was
+/// in is still there. A directory whose deletion takes longer than this
+/// loses the files deleted first from its snapshot.
+const PRUNE_DELAY: Duration = Duration::from_secs(5 * 60);
+
+/// Minimum time between two checks for snapshots past their retention.
+const RETENTION_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const SNAPSHOT_INFO: &str = "snapshot.json";
+
+/// Maintains the mirror of one root.
+pub(crate) struct Shadow {
+    dir: PathBuf,
+    /// The root as configured, which event paths start with
+    root: PathBuf,
+    /// Where the root is mirrored
+    mirror: PathBuf,
+    /// The shadow directory as a path under the root, if it is inside it
+    within_root: Option<PathBuf>,
+    retention: Duration,
+    /// Deleted paths waiting to be dropped from the mirror
+    pruning: VecDeque<(PathBuf, Instant)>,
+    last_retention_check: Option<Instant>,
+}
+
+/// What `snapshot.json` records about a snapshot.
+#[derive(Serialize, Deserialize)]
+struct SnapshotInfo {
+    root: String,
+    original: PathBuf,
+    removed_at: String,
+}
+
+/// A removed directory kept in a shadow directory.
+#[derive(Debug, Clone)]
+pub struct Snapshot {
+    pub id: String,
+    /// Name of the root the directory was removed from
+    pub root: String,
+    /// Where the directory was
+    pub original: PathBuf,
+    pub removed_at: DateTime<FixedOffset>,
+    /// The kept contents
+    pub path: PathBuf,
+}
+
+impl Shadow {
+    /// Sets up the mirror of the root at `root`, which is at `absolute_root`,
+    /// in the shadow directory `dir`.
+    pub fn open(
+        dir: &Path,
+        root: &Path,
+        absolute_root: &Path,
+        retention: Duration,
+    ) -> Result<Shadow, Error> {
+        let relative: PathBuf = absolute_root
+            .components()
+            .filter(|c| matches!(c, Component::Normal(_)))
+            .collect();
+        let mirror = dir.join("mirror").join(relative);
+        let create = |path: &Path| {
+            fs::create_dir_all(path).map_err(|source| Error::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+        };
+        create(dir)?;
+        if !same_filesystem(dir, root) {
+            return Err(Error::Invalid(format!(
+                "shadow directory {} must be on the same filesystem as {}",
+                dir.display(),
+                root.display()
+            )));
+        }
+        create(&mirror)?;
+        create(&dir.join("removed"))?;
+        // Compared as canonical paths, so that the shadow directory is found
+        // inside the root however either of them was written
+        let within_root = match (dir.canonicalize(), absolute_root.canonicalize()) {
+            (Ok(dir), Ok(absolute_root)) => dir
+                .strip_prefix(&absolute_root)
+                .ok()
+                .map(|rest| root.join(rest)),
+            _ => None,
+        };
+        Ok(Shadow {
+            dir: dir.to_path_buf(),
+            root: root.to_path_buf(),
+            mirror,
+            within_root,
+            retention,
+            pruning: VecDeque::new(),
+            last_retention_check: None,
+        })
+    }
+
+    /// Whether `path`, a path under the root, is part of the shadow
+    /// directory, which is never mirrored, tracked or counted itself.
+    pub fn contains(&self, path: &Path) -> bool {
+        self.within_root
+            .as_ref()
+            .is_some_and(|dir| path.starts_with(dir))
+    }
+
+    /// The shadow directory as a path under the root, if it is inside it.
+    pub fn within_root(&self) -> Option<&Path> {
+        self.within_root.as_deref()
+    }
+
+    fn mirror_path(&self, path: &Path) -> Option<PathBuf> {
+        let rest = path.strip_prefix(&self.root).ok()?;
+        Some(self.mirror.join(rest))
+    }
+
+    /// Links the file at `path` into the mirror, or everything inside the
+    /// directory at `path`. Paths for which `skip` holds are left out, along
+    /// with everything below them.
+    pub fn sync(&self, path: &Path, skip: impl Fn(&Path) -> bool) -> io::Result<()> {
+        let mut first_error = None;
+        let mut entries = WalkDir::new(path).into_iter();
+        while let Some(entry) = entries.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            if skip(entry.path()) || self.contains(entry.path()) {
+                if entry.file_type().is_dir() {
+                    entries.skip_current_dir();
+                }
+                continue;
+            }
+            let Some(target) = self.mirror_path(entry.path()) else {
+                continue;
+            };
+            let result = if entry.file_type().is_dir() {
+                fs::create_dir_all(&target)
+            } else if entry.file_type().is_file() {
+                link(entry.path(), &target)
+            } else {
+                Ok(())
+            };
+            if let Err(e) = result {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Follows a rename in the mirror.
+    pub fn rename(&self, from: &Path, to: &Path, skip: impl Fn(&Path) -> bool) -> io::Result<()> {
+        let (Some(old), Some(new)) = (self.mirror_path(from), self.mirror_path(to)) else {
+            return Ok(());
+        };
+        if old.symlink_metadata().is_ok() && new.symlink_metadata().is_err() {
+            if let Some(parent) = new.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::rename(&old, &new)?;
+        }
+        self.sync(to, skip)
+    }
+
+    /// Drops `path` from the mirror once it has been gone for a while.
+    pub fn forget(&mut self, path: &Path) {
+        self.pruning.push_back((path.to_path_buf(), Instant::now()));
+    }
+
+    /// Drops deleted paths that are due from the mirror and deletes expired
+    /// snapshots.
+    pub fn expire(&mut self) -> io::Result<()> {
+        let mut first_error = None;
+        while let Some((_, seen)) = self.pruning.front() {
+            if seen.elapsed() < PRUNE_DELAY {
+                break;
+            }
+            let Some((path, _)) = self.pruning.pop_front() else {
+                break;
+            };
+            // Deleted along with its parent, which is kept or pruned as a whole
+            if path.symlink_metadata().is_ok() || !path.parent().is_some_and(Path::exists) {
+                continue;
+            }
+            if let Some(target) = self.mirror_path(&path) {
+                if let Err(e) = remove(&target) {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+
+        if self
+            .last_retention_check
+            .is_none_or(|checked| checked.elapsed() >= RETENTION_CHECK_INTERVAL)
+        {
+            self.last_retention_check = Some(Instant::now());
+            if let Err(e) = self.delete_expired_snapshots() {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    fn delete_expired_snapshots(&self) -> io::Result<()> {
+        let Some(cutoff) = SystemTime::now().checked_sub(self.retention) else {
+            return Ok(());
+        };
+        let cutoff = DateTime::<Utc>::from(cutoff);
+        for entry in fs::read_dir(self.dir.join("removed"))?.flatten() {
+            match read_info(&entry.path()) {
+                Some(info) if info.removed_at < cutoff => fs::remove_dir_all(entry.path())?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops everything from the mirror that is no longer under the root,
+    /// for files deleted while no monitor was running.
+    pub fn prune_stale(&self) -> io::Result<()> {
+        let mut first_error = None;
+        let mut entries = WalkDir::new(&self.mirror).min_depth(1).into_iter();
+        while let Some(entry) = entries.next() {
+            let Ok(entry) = entry else {
+                continue;
+            };
+            let Ok(rest) = entry.path().strip_prefix(&self.mirror) else {
+                continue;
+            };
+            let source = self.root.join(rest);
+            let stale = match source.symlink_metadata() {
+                Ok(meta) => meta.is_dir() != entry.file_type().is_dir(),
+                Err(_) => true,
+            };
+            if stale {
+                if entry.file_type().is_dir() {
+                    entries.skip_current_dir();
+                }
+                if let Err(e) = remove(entry.path()) {
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    /// Moves the mirror of the removed directory `path` into a new snapshot
+    /// and returns its id. `original` is where the directory was, as an
+    /// absolute path.
+    pub fn keep(&self, path: &Path, original: &Path, root: &str) -> io::Result<Option<String>> {
+        let Some(source) = self.mirror_path(path).filter(|p| p.is_dir()) else {
+            return Ok(None);
+        };
+        let now = Utc::now();
+        let stamp = now.format("%Y%m%dT%H%M%S").to_string();
+        let removed = self.dir.join("removed");
+        let (id, dir) = (1..)
+            .map(|n| {
+                let id = format!("{}-{}", stamp, n);
+                (removed.join(&id), id)
+            })
+            .find_map(|(dir, id)| match fs::create_dir(&dir) {
+                Ok(()) => Some(Ok((id, dir))),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => None,
+                Err(e) => Some(Err(e)),
+            })
+            .expect("some snapshot id is free")?;
+
+        let name = original.file_name().unwrap_or(path.as_os_str());
+        fs::rename(&source, dir.join(name))?;
+        let info = SnapshotInfo {
+            root: root.to_string(),
+            original: original.to_path_buf(),
+            removed_at: now.to_rfc3339_opts(SecondsFormat::Secs, true),
+        };
+        fs::write(dir.join(SNAPSHOT_INFO), serde_json::to_vec_pretty(&info)?)?;
+        Ok(Some(id))
+    }
+}
+
+impl Snapshot {
+    /// The snapshots in the shadow directory `dir`, oldest first.
+    pub fn list(dir: &Path) -> Result<Vec<Snapshot>, Error> {
+        let removed = dir.join("removed");
+        let entries = fs::read_dir(&removed).map_err(|source| Error::Io {
+            path: removed.clone(),
+            source,
+        })?;
+        let mut snapshots: Vec<Snapshot> = entries
+            .flatten()
+            .filter_map(|entry| Snapshot::read(&entry.path()))
+            .collect();
+        snapshots.sort_by(|a, b| (a.removed_at, &a.id).cmp(&(b.removed_at, &b.id)));
+        Ok(snapshots)
+    }
+
+    /// The snapshot called `id` in the shadow directory `dir`.
+    pub fn find(dir: &Path, id: &str) -> Result<Snapshot, Error> {
+        let path = dir.join("removed").join(id);
+        Snapshot::read(&path)
+            .filter(|_| !id.contains(['/', '\\']))
+            .ok_or_else(|| Error::Invalid(format!("no snapshot {} in {}", id, dir.display())))
+    }
+
+    fn read(path: &Path) -> Option<Snapshot> {
+        let info = read_info(path)?;
+        let name = info.original.file_name()?;
+        Some(Snapshot {
+            id: path.file_name()?.to_string_lossy().into_owned(),
+            root: info.root,
+            path: path.join(name),
+            original: info.original,
+            removed_at: info.removed_at.fixed_offset(),
+        })
+    }
+
+    /// Moves the kept directory to `to` and deletes the snapshot.
+    pub fn restore(&self, to: &Path) -> Result<(), Error> {
+        if to.exists() {
+            return Err(Error::Invalid(format!("{} already exists", to.display())));
+        }
+        fs::rename(&self.path, to).map_err(|source| Error::Io {
+            path: to.to_path_buf(),
+            source,
+        })?;
+        let dir = self.path.parent().unwrap_or(&self.path);
+        fs::remove_dir_all(dir).map_err(|source| Error::Io {
+            path: dir.to_path_buf(),
+            source,
+        })
+    }
+}
+
+/// `snapshot.json` of the snapshot at `dir`, with its time parsed.
+fn read_info(dir: &Path) -> Option<ParsedInfo> {
+    let text = fs::read(dir.join(SNAPSHOT_INFO)).ok()?;
+    let info: SnapshotInfo = serde_json::from_slice(&text).ok()?;
+    Some(ParsedInfo {
+        removed_at: DateTime::parse_from_rfc3339(&info.removed_at)
+            .ok()?
+            .to_utc(),
+        root: info.root,
+        original: info.original,
+    })
+}
+
+struct ParsedInfo {
+    root: String,
+    original: PathBuf,
+    removed_at: DateTime<Utc>,
+}
+
+/// Makes `target` a hard link to `source` unless it already is one.
+fn link(source: &Path, target: &Path) -> io::Result<()> {
+    if let (Ok(a), Ok(b)) = (fs::metadata(source), fs::symlink_metadata(target)) {
+        if same_file(&a, &b) {
+            return Ok(());
+        }
+        remove(target)?;
+    }
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::hard_link(source, target)
+}
+
+/// Deletes a file or a whole directory; a path that is already gone is
+/// fine.
+fn remove(path: &Path) -> io::Result<()> {
+    let result = match fs::symlink_metadata(path) {
+        Ok(meta) if meta.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(e) => Err(e),
+    };
+    match result {
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        other => other,
+    }
+}
+
+#[cfg(unix)]
+fn same_file(a: &fs::Metadata, b: &fs::Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &fs::Metadata, _b: &fs::Metadata) -> bool {
+    false
+}
+
+#[cfg(unix)]
+fn same_filesystem(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev(),
+        _ => false,
+    }
+}
+
+/// Hard links fail across volumes anyway, with a clear enough error.
+#[cfg(not(unix))]
+fn same_filesystem(_a: &Path, _b: &Path) -> bool {
+    true
+}
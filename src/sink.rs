@@ -0,0 +1,305 @@
+use crate::{
+    log::{FileSink, JsonRecord, LogRecord},
+    syslog::SyslogSink,
+    webhook::WebhookSink,
+};
+use chrono::{DateTime, FixedOffset};
+use serde::Serialize;
+use std::{
+    collections::{HashSet, VecDeque},
+    fs::OpenOptions,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError, Sender},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+/// One event ready to be written out.
+#[derive(Debug, Clone)]
+pub struct Entry {
+    /// Name of the root the event happened in
+    pub root: String,
+    /// When the event was detected, in the configured timezone
+    pub time: DateTime<FixedOffset>,
+    /// `time` in the configured timestamp format
+    pub timestamp: String,
+    pub record: LogRecord,
+}
+
+/// A destination for log entries.
+pub trait EventSink: Send {
+    /// Names the sink in error messages, e.g. `log dirmon_log.csv`.
+    fn describe(&self) -> String;
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()>;
+}
+
+/// Prints one line per entry to standard output.
+pub struct StdoutSink;
+
+impl EventSink for StdoutSink {
+    fn describe(&self) -> String {
+        "stdout".to_string()
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let mut out = io::stdout().lock();
+        writeln!(
+            out,
+            "{} [{}] {}",
+            entry.timestamp, entry.root, entry.record.message
+        )?;
+        out.flush()
+    }
+}
+
+/// Wait before retrying a sink after its first failed write; doubled after
+/// every further failure up to `RETRY_MAX`.
+const RETRY_MIN: Duration = Duration::from_secs(1);
+const RETRY_MAX: Duration = Duration::from_secs(60);
+
+/// Entries a failing sink keeps in memory; older ones are spilled to the
+/// fallback file.
+const MAX_BUFFERED: usize = 10_000;
+
+/// How long a failing sink holds on to an entry before spilling it to the
+/// fallback file, so that entries are kept even if dirmon is killed before
+/// the sink recovers.
+const SPILL_AFTER: Duration = Duration::from_secs(300);
+
+/// A sink running on its own thread.
+struct Worker {
+    /// Roots whose entries the sink receives; all of them when `None`
+    roots: Option<HashSet<String>>,
+    tx: Sender<Arc<Entry>>,
+    thread: JoinHandle<()>,
+}
+
+/// Hands every entry to any number of sinks.
+///
+/// Each sink writes on its own thread, so one that is slow or failing, like
+/// an unreachable webhook, never holds up the others. A sink that fails is
+/// retried with backoff while its entries are buffered; failures and
+/// recoveries are reported on stderr.
+pub struct FanOut {
+    workers: Vec<Worker>,
+    fallback: Arc<Fallback>,
+}
+
+impl FanOut {
+    /// Entries that cannot be buffered any more are appended to `fallback`.
+    pub fn new(fallback: PathBuf) -> FanOut {
+        FanOut {
+            workers: Vec::new(),
+            fallback: Arc::new(Fallback {
+                path: fallback,
+                lock: Mutex::new(()),
+            }),
+        }
+    }
+
+    /// Adds `sink`, receiving entries of `roots` only if given.
+    pub fn add(&mut self, sink: Box<dyn EventSink>, roots: Option<HashSet<String>>) {
+        let (tx, rx) = mpsc::channel::<Arc<Entry>>();
+        let fallback = Arc::clone(&self.fallback);
+        let thread = thread::spawn(move || deliver(sink, rx, &fallback));
+        self.workers.push(Worker { roots, tx, thread });
+    }
+
+    pub fn send(&self, entry: Entry) {
+        let entry = Arc::new(entry);
+        for worker in &self.workers {
+            let wanted = match &worker.roots {
+                Some(roots) => roots.contains(&entry.root),
+                None => true,
+            };
+            if wanted {
+                // A worker only stops when its sink panicked
+                let _ = worker.tx.send(Arc::clone(&entry));
+            }
+        }
+    }
+}
+
+impl Drop for FanOut {
+    /// Waits for every sink to write the entries it was sent, or to spill
+    /// them to the fallback file.
+    fn drop(&mut self) {
+        for worker in self.workers.drain(..) {
+            drop(worker.tx);
+            let _ = worker.thread.join();
+        }
+    }
+}
+
+/// Writes entries from `rx` to `sink` until the channel closes, retrying
+/// failed writes with exponential backoff. Entries still not written after
+/// [`SPILL_AFTER`] go to the fallback file.
+fn deliver(mut sink: Box<dyn EventSink>, rx: Receiver<Arc<Entry>>, fallback: &Fallback) {
+    let name = sink.describe();
+    // Entries with when they were received
+    let mut backlog: VecDeque<(Arc<Entry>, Instant)> = VecDeque::new();
+    let mut retry = RETRY_MIN;
+    let mut failing = false;
+    let mut open = true;
+
+    while open || !backlog.is_empty() {
+        if backlog.is_empty() {
+            match rx.recv() {
+                Ok(entry) => backlog.push_back((entry, Instant::now())),
+                Err(_) => break,
+            }
+        }
+        backlog.extend(rx.try_iter().map(|entry| (entry, Instant::now())));
+
+        let error = loop {
+            let Some((entry, _)) = backlog.front() else {
+                break None;
+            };
+            match sink.write(entry) {
+                Ok(()) => {
+                    backlog.pop_front();
+                }
+                Err(e) => break Some(e),
+            }
+        };
+        let Some(error) = error else {
+            if failing {
+                eprintln!("dirmon: {} is writable again", name);
+                failing = false;
+                retry = RETRY_MIN;
+            }
+            continue;
+        };
+
+        if !failing {
+            eprintln!(
+                "dirmon: cannot write to {}: {}; buffering entries and retrying",
+                name, error
+            );
+            failing = true;
+        }
+        if !open {
+            // Shutting down, so there is no later attempt to wait for
+            fallback.spill(&name, backlog.drain(..).map(|(entry, _)| entry));
+            break;
+        }
+
+        // Collect new entries until the next attempt
+        let retry_at = Instant::now() + retry;
+        while let Some(left) = retry_at.checked_duration_since(Instant::now()) {
+            match rx.recv_timeout(left) {
+                Ok(entry) => backlog.push_back((entry, Instant::now())),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    open = false;
+                    break;
+                }
+            }
+        }
+        let waited = backlog
+            .iter()
+            .take_while(|(_, received)| received.elapsed() >= SPILL_AFTER)
+            .count();
+        let excess = backlog.len().saturating_sub(MAX_BUFFERED).max(waited);
+        if excess > 0 {
+            fallback.spill(&name, backlog.drain(..excess).map(|(entry, _)| entry));
+        }
+        retry = (retry * 2).min(RETRY_MAX);
+    }
+}
+
+/// File taking the entries that a failing sink cannot hold any more.
+struct Fallback {
+    path: PathBuf,
+    // Sinks spill from their own threads
+    lock: Mutex<()>,
+}
+
+/// A line of the fallback file: the entry as in JSON Lines logs, and the
+/// sink it was meant for.
+#[derive(Serialize)]
+struct SpilledRecord<'a> {
+    sink: &'a str,
+    #[serde(flatten)]
+    record: JsonRecord<'a>,
+}
+
+impl Fallback {
+    fn spill(&self, sink: &str, entries: impl Iterator<Item = Arc<Entry>>) {
+        let entries: Vec<_> = entries.collect();
+        let _guard = self.lock.lock().unwrap_or_else(|e| e.into_inner());
+        let written = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .and_then(|file| {
+                let mut writer = BufWriter::new(file);
+                for entry in &entries {
+                    let line = SpilledRecord {
+                        sink,
+                        record: JsonRecord::new(entry),
+                    };
+                    serde_json::to_writer(&mut writer, &line)?;
+                    writer.write_all(b"\n")?;
+                }
+                writer.flush()
+            });
+        match written {
+            Ok(()) => eprintln!(
+                "dirmon: moved {} entries for {} to {}",
+                entries.len(),
+                sink,
+                self.path.display()
+            ),
+            Err(e) => eprintln!(
+                "dirmon: lost {} entries for {}, cannot write to {}: {}",
+                entries.len(),
+                sink,
+                self.path.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// The kinds of sink that can be configured.
+#[derive(Debug, Clone)]
+pub enum SinkKind {
+    File(FileSink),
+    Stdout,
+    Syslog {
+        facility: String,
+        /// Remote syslog host:port; the local socket when `None`
+        server: Option<String>,
+    },
+    Webhook {
+        url: String,
+        timeout: Duration,
+    },
+}
+
+/// A configured sink and the roots it receives entries for.
+#[derive(Debug, Clone)]
+pub struct SinkSettings {
+    pub kind: SinkKind,
+    /// All roots when `None`
+    pub roots: Option<HashSet<String>>,
+}
+
+impl SinkKind {
+    pub fn open(&self) -> Result<Box<dyn EventSink>, String> {
+        Ok(match self {
+            SinkKind::File(file) => Box::new(file.clone()),
+            SinkKind::Stdout => Box::new(StdoutSink),
+            SinkKind::Syslog { facility, server } => {
+                Box::new(SyslogSink::connect(facility, server.as_deref())?)
+            }
+            SinkKind::Webhook { url, timeout } => Box::new(WebhookSink::new(url.clone(), *timeout)),
+        })
+    }
+}
@@ -0,0 +1,134 @@
+use crate::{
+    fingerprint::{ChildPrint, Fingerprint},
+    identity::DirIdentity,
+    manifest::Totals,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    time::SystemTime,
+};
+
+/// What is remembered about a tracked directory, so it can be recognised
+/// after it moves.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(from = "SavedSnapshot")]
+pub struct DirSnapshot {
+    /// Lineage id, given when the directory is first tracked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub identity: Option<DirIdentity>,
+    pub mtime: Option<SystemTime>,
+    /// Combined size of the files directly inside the directory
+    pub size: u64,
+    /// The immediate children by name, with their sizes and modification
+    /// times, for recognising the directory under another name
+    #[serde(default)]
+    pub fingerprint: Fingerprint,
+    /// Files inside the immediate subdirectories that are too deep to be
+    /// tracked, by name; not taken by [`DirSnapshot::take`], as it means
+    /// reading them through
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub subdirs: BTreeMap<String, Totals>,
+}
+
+/// A snapshot as saved by any version. Older ones also listed the names of
+/// the children, which are now taken from the fingerprint.
+#[derive(Deserialize)]
+struct SavedSnapshot {
+    #[serde(default)]
+    id: Option<String>,
+    identity: Option<DirIdentity>,
+    mtime: Option<SystemTime>,
+    size: u64,
+    #[serde(default)]
+    children: BTreeSet<String>,
+    #[serde(default)]
+    fingerprint: Fingerprint,
+    #[serde(default)]
+    subdirs: BTreeMap<String, Totals>,
+}
+
+impl From<SavedSnapshot> for DirSnapshot {
+    fn from(saved: SavedSnapshot) -> DirSnapshot {
+        let mut fingerprint = saved.fingerprint;
+        for name in saved.children {
+            if !fingerprint.contains(&name) {
+                fingerprint.insert(name, ChildPrint::new(None, None));
+            }
+        }
+        DirSnapshot {
+            id: saved.id,
+            identity: saved.identity,
+            mtime: saved.mtime,
+            size: saved.size,
+            fingerprint,
+            subdirs: saved.subdirs,
+        }
+    }
+}
+
+impl DirSnapshot {
+    /// Records the directory at `path`. Unreadable parts are left empty.
+    pub fn take(path: &Path) -> DirSnapshot {
+        let meta = std::fs::metadata(path).ok();
+        let mut snapshot = DirSnapshot {
+            identity: meta.as_ref().and_then(DirIdentity::from_metadata),
+            mtime: meta.and_then(|m| m.modified().ok()),
+            ..DirSnapshot::default()
+        };
+
+        if let Ok(entries) = std::fs::read_dir(path) {
+            for entry in entries.flatten() {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let child = match entry.metadata() {
+                    Ok(meta) => {
+                        let size = meta.is_file().then_some(meta.len());
+                        snapshot.size += size.unwrap_or(0);
+                        ChildPrint::new(size, meta.modified().ok())
+                    }
+                    Err(_) => ChildPrint::new(None, None),
+                };
+                snapshot.fingerprint.insert(name, child);
+            }
+        }
+        snapshot
+    }
+
+    /// Scores from 0.0 to 1.0 how likely `other` is this directory.
+    ///
+    /// A matching identity is conclusive. Otherwise the score weighs the
+    /// overlap of child names most, then total size, then mtime. Two empty
+    /// directories share no children, so there is nothing to match on.
+    pub fn similarity(&self, other: &DirSnapshot) -> f64 {
+        if self.identity.is_some() && self.identity == other.identity {
+            return 1.0;
+        }
+
+        let shared = self
+            .fingerprint
+            .names()
+            .filter(|name| other.fingerprint.contains(name))
+            .count();
+        let total = self.fingerprint.len() + other.fingerprint.len() - shared;
+        let children = if total == 0 {
+            0.0
+        } else {
+            shared as f64 / total as f64
+        };
+
+        let size = if self.size == other.size {
+            1.0
+        } else {
+            self.size.min(other.size) as f64 / self.size.max(other.size) as f64
+        };
+
+        let mtime = match (self.mtime, other.mtime) {
+            (Some(a), Some(b)) if a == b => 1.0,
+            _ => 0.0,
+        };
+
+        0.5 * children + 0.3 * size + 0.2 * mtime
+    }
+}
@@ -0,0 +1,76 @@
+use crate::{error::Error, lineage::Step, snapshot::DirSnapshot};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+/// Known directories of every root, saved so that changes made while dirmon
+/// was not running can be logged on the next start.
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct State {
+    /// Keyed by root name
+    pub roots: BTreeMap<String, RootState>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct RootState {
+    /// Path of the root when the state was saved; state saved for a
+    /// different path is not reconciled
+    pub path: PathBuf,
+    /// Where `path` was, so that the saved paths, which start with it, can
+    /// be told apart from any other directory's
+    #[serde(default)]
+    pub absolute_path: Option<PathBuf>,
+    /// Levels below the root that were tracked, `None` for all of them;
+    /// state from before depths were configurable covers the top level
+    #[serde(default = "RootState::top_level")]
+    pub depth: Option<usize>,
+    pub directories: HashMap<PathBuf, DirSnapshot>,
+    /// When the directories were last seen as saved
+    #[serde(default)]
+    pub saved_at: Option<SystemTime>,
+    /// Where each directory tracked so far has been, by id
+    #[serde(default)]
+    pub lineage: BTreeMap<String, Vec<Step>>,
+}
+
+impl RootState {
+    fn top_level() -> Option<usize> {
+        Some(1)
+    }
+}
+
+impl State {
+    /// Reads the state file, or returns an empty state if there is none yet.
+    pub fn load(path: &Path) -> Result<State, Error> {
+        let text = match std::fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(State::default()),
+            Err(source) => {
+                return Err(Error::Io {
+                    path: path.to_path_buf(),
+                    source,
+                })
+            }
+        };
+        serde_json::from_str(&text)
+            .map_err(|e| Error::Invalid(format!("invalid state file {}: {}", path.display(), e)))
+    }
+
+    /// Writes the state to a temporary file first, so an interrupted save
+    /// never leaves a truncated state behind.
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let text = serde_json::to_string(self)
+            .map_err(|e| Error::Invalid(format!("cannot serialize state: {}", e)))?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, text)
+            .and_then(|()| std::fs::rename(&tmp, path))
+            .map_err(|source| Error::Io {
+                path: path.to_path_buf(),
+                source,
+            })
+    }
+}
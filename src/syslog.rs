@@ -0,0 +1,53 @@
+use crate::{
+    log::RecordKind,
+    sink::{Entry, EventSink},
+};
+use std::io;
+use syslog::{Facility, Formatter3164, Logger, LoggerBackend};
+
+/// Sends entries to the system logger, or to a remote one over UDP.
+pub struct SyslogSink {
+    logger: Logger<LoggerBackend, Formatter3164>,
+    target: String,
+}
+
+impl SyslogSink {
+    /// Connects to the local syslog socket, or to `server` (host:port) if
+    /// given.
+    pub fn connect(facility: &str, server: Option<&str>) -> Result<SyslogSink, String> {
+        let facility: Facility = facility
+            .parse()
+            .map_err(|()| format!("unknown syslog facility '{}'", facility))?;
+        let formatter = Formatter3164 {
+            facility,
+            hostname: None,
+            process: "dirmon".to_string(),
+            pid: std::process::id(),
+        };
+        let (logger, target) = match server {
+            Some(server) => (
+                syslog::udp(formatter, "0.0.0.0:0", server),
+                format!("syslog {}", server),
+            ),
+            None => (syslog::unix(formatter), "syslog".to_string()),
+        };
+        let logger = logger.map_err(|e| format!("cannot connect to {}: {}", target, e))?;
+        Ok(SyslogSink { logger, target })
+    }
+}
+
+impl EventSink for SyslogSink {
+    fn describe(&self) -> String {
+        self.target.clone()
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let message = format!("[{}] {}", entry.root, entry.record.message);
+        let sent = match entry.record.kind {
+            RecordKind::Error => self.logger.err(message),
+            RecordKind::Started => self.logger.info(message),
+            _ => self.logger.notice(message),
+        };
+        sent.map_err(|e| io::Error::other(e.to_string()))
+    }
+}
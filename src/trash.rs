@@ -0,0 +1,405 @@
+//! Folders deleted to a freedesktop.org Trash.
+//!
+//! A trash directory holds the deleted items in `files/` and, for each one,
+//! a `.trashinfo` file in `info/` recording where it came from and when it
+//! was deleted. File managers write the info file before moving the item,
+//! so it is there by the time the folder is seen to vanish.
+
+use crate::error::Error;
+use chrono::{Local, NaiveDateTime, TimeZone};
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime},
+};
+
+const INFO_EXTENSION: &str = "trashinfo";
+const DELETION_DATE_FORMAT: &str = "%Y-%m-%dT%H:%M:%S";
+const DELETION_DATE_LEEWAY: Duration = Duration::from_secs(2);
+
+/// A trashed item and what its `.trashinfo` file says about it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrashInfo {
+    /// Where the item was before it was deleted
+    pub original: PathBuf,
+    /// When it was deleted, in the deleting machine's local time
+    pub deleted_at: Option<NaiveDateTime>,
+    /// The item inside the trash's `files/` directory
+    pub trashed: PathBuf,
+    /// The `.trashinfo` file
+    pub info: PathBuf,
+}
+
+impl TrashInfo {
+    /// Reads the `.trashinfo` file at `info`.
+    pub fn read(info: &Path) -> Result<TrashInfo, Error> {
+        let io_error = |source| Error::Io {
+            path: info.to_path_buf(),
+            source,
+        };
+        let text = std::fs::read_to_string(info).map_err(io_error)?;
+        TrashInfo::parse(info, &text)
+    }
+
+    /// Reads `text`, the contents of the `.trashinfo` file at `info`.
+    fn parse(info: &Path, text: &str) -> Result<TrashInfo, Error> {
+        let invalid = || Error::Invalid(format!("{} is not a trash info file", info.display()));
+
+        let trash = info.parent().and_then(Path::parent).ok_or_else(invalid)?;
+        let name = info
+            .file_stem()
+            .filter(|_| info.extension().is_some_and(|e| e == INFO_EXTENSION))
+            .ok_or_else(invalid)?;
+
+        let mut in_section = false;
+        let mut original = None;
+        let mut deleted_at = None;
+        for line in text.lines().map(str::trim) {
+            if line.starts_with('[') {
+                in_section = line == "[Trash Info]";
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+            match line.split_once('=') {
+                Some(("Path", value)) => original = Some(decode_path(value).ok_or_else(invalid)?),
+                Some(("DeletionDate", value)) => {
+                    deleted_at = NaiveDateTime::parse_from_str(value, DELETION_DATE_FORMAT).ok()
+                }
+                _ => {}
+            }
+        }
+        let original: PathBuf = original.ok_or_else(invalid)?;
+        Ok(TrashInfo {
+            // Trash directories on other volumes may record paths relative
+            // to the top of the volume
+            original: if original.is_absolute() {
+                original
+            } else {
+                top_directory(trash).join(original)
+            },
+            deleted_at,
+            trashed: trash.join("files").join(name),
+            info: info.to_path_buf(),
+        })
+    }
+
+    /// The info for `path`, given either as the trashed item or as its
+    /// `.trashinfo` file.
+    pub fn find(path: &Path) -> Result<TrashInfo, Error> {
+        if path.extension().is_some_and(|e| e == INFO_EXTENSION) && path.is_file() {
+            return TrashInfo::read(path);
+        }
+        info_path(path)
+            .map(|info| TrashInfo::read(&info))
+            .unwrap_or_else(|| {
+                Err(Error::Invalid(format!(
+                    "{} is not in the files directory of a trash",
+                    path.display()
+                )))
+            })
+    }
+
+    /// Whether the item was deleted after `time`. The deletion date is kept
+    /// in whole seconds of local time, so it is given a little leeway; an
+    /// item without one cannot be placed and never is.
+    fn deleted_after(&self, time: SystemTime) -> bool {
+        let Some(deleted_at) = self
+            .deleted_at
+            .and_then(|d| Local.from_local_datetime(&d).earliest())
+        else {
+            return false;
+        };
+        SystemTime::from(deleted_at) + DELETION_DATE_LEEWAY >= time
+    }
+
+    /// The info for `path` if it is an item in a trash.
+    pub fn for_trashed(path: &Path) -> Option<TrashInfo> {
+        TrashInfo::read(&info_path(path)?).ok()
+    }
+
+    /// Moves the item back to where it was deleted from and drops its info
+    /// file.
+    pub fn restore(&self) -> Result<(), Error> {
+        if self.original.exists() {
+            return Err(Error::Invalid(format!(
+                "{} already exists",
+                self.original.display()
+            )));
+        }
+        std::fs::rename(&self.trashed, &self.original).map_err(|source| Error::Io {
+            path: self.original.clone(),
+            source,
+        })?;
+        std::fs::remove_file(&self.info).map_err(|source| Error::Io {
+            path: self.info.clone(),
+            source,
+        })
+    }
+}
+
+/// The `.trashinfo` file that goes with an item in a trash's `files/`.
+fn info_path(trashed: &Path) -> Option<PathBuf> {
+    let files = trashed.parent()?;
+    if files.file_name()? != "files" {
+        return None;
+    }
+    let mut name = OsString::from(trashed.file_name()?);
+    name.push(".");
+    name.push(INFO_EXTENSION);
+    let info = files.parent()?.join("info").join(name);
+    info.is_file().then_some(info)
+}
+
+/// The directory relative `Path` entries of `trash` are based on: the
+/// volume top holding `.Trash-$uid` or `.Trash/$uid`.
+fn top_directory(trash: &Path) -> &Path {
+    let parent = trash.parent().unwrap_or(trash);
+    if parent.file_name().is_some_and(|n| n == ".Trash") {
+        parent.parent().unwrap_or(parent)
+    } else {
+        parent
+    }
+}
+
+/// Undoes the URL-style escaping of `Path` values; `None` unless every `%`
+/// starts an escape of two hex digits.
+fn decode_path(value: &str) -> Option<PathBuf> {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = bytes
+                .get(i + 1..i + 3)
+                .filter(|hex| hex.iter().all(u8::is_ascii_hexdigit))?;
+            decoded.push(u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(bytes_to_path(decoded))
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+    PathBuf::from(OsString::from_vec(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: Vec<u8>) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+/// The trash directories a folder deleted from under `root` may have gone
+/// to: the user's home trash and those of every user at the top of the
+/// volume `root` is on. `extra` are other places to check, such as search
+/// roots, kept if they look like a trash.
+pub fn trash_directories(root: &Path, extra: &[PathBuf]) -> Vec<PathBuf> {
+    let mut candidates = Vec::new();
+    let data_home = std::env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/share")));
+    candidates.extend(data_home.map(|data| data.join("Trash")));
+
+    if let Some(top) = volume_top(root) {
+        if let Ok(entries) = std::fs::read_dir(top.join(".Trash")) {
+            candidates.extend(entries.flatten().map(|e| e.path()));
+        }
+        if let Ok(entries) = std::fs::read_dir(&top) {
+            candidates.extend(
+                entries
+                    .flatten()
+                    .filter(|e| e.file_name().to_string_lossy().starts_with(".Trash-"))
+                    .map(|e| e.path()),
+            );
+        }
+    }
+    candidates.extend(extra.iter().cloned());
+
+    let mut trashes: Vec<PathBuf> = Vec::new();
+    for candidate in candidates {
+        if candidate.join("files").is_dir()
+            && candidate.join("info").is_dir()
+            && !trashes.contains(&candidate)
+        {
+            trashes.push(candidate);
+        }
+    }
+    trashes
+}
+
+/// Looks in `trashes` for the item deleted from `original`, which must be
+/// absolute. `matches` tells whether an item is the directory that
+/// vanished, when that can be told, and only such an item is taken;
+/// otherwise only one deleted after `seen`, when the directory was last
+/// known to be there. When several qualify, the latest deletion wins.
+pub fn find_trashed(
+    original: &Path,
+    trashes: &[PathBuf],
+    matches: impl Fn(&Path) -> Option<bool>,
+    seen: SystemTime,
+) -> Option<TrashInfo> {
+    let name = original.file_name()?.to_string_lossy();
+    let original = canonical_location(original);
+    let mut found: Vec<TrashInfo> = Vec::new();
+    for trash in trashes {
+        let Ok(entries) = std::fs::read_dir(trash.join("info")) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            // Items keep their name in the trash, with a suffix added on
+            // clashes
+            if !entry.file_name().to_string_lossy().starts_with(&*name) {
+                continue;
+            }
+            let Ok(info) = TrashInfo::read(&entry.path()) else {
+                continue;
+            };
+            if canonical_location(&info.original) != original || !info.trashed.is_dir() {
+                continue;
+            }
+            let is_it = match matches(&info.trashed) {
+                Some(is_it) => is_it,
+                None => info.deleted_after(seen),
+            };
+            if is_it {
+                found.push(info);
+            }
+        }
+    }
+    found.sort_by_key(|info| info.deleted_at);
+    found.pop()
+}
+
+/// `path` with its parent resolved, so the same location spelled through a
+/// symlink compares equal. Also works once `path` itself is gone.
+fn canonical_location(path: &Path) -> PathBuf {
+    match (path.parent().map(std::fs::canonicalize), path.file_name()) {
+        (Some(Ok(parent)), Some(name)) => parent.join(name),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// The mount point of the filesystem `path` is on.
+#[cfg(unix)]
+fn volume_top(path: &Path) -> Option<PathBuf> {
+    use std::os::unix::fs::MetadataExt;
+
+    let path = std::fs::canonicalize(path).ok()?;
+    let dev = std::fs::metadata(&path).ok()?.dev();
+    let mut top = path.as_path();
+    while let Some(parent) = top.parent() {
+        if std::fs::metadata(parent).ok()?.dev() != dev {
+            break;
+        }
+        top = parent;
+    }
+    Some(top.to_path_buf())
+}
+
+#[cfg(not(unix))]
+fn volume_top(_path: &Path) -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INFO: &str = "/home/ann/.local/share/Trash/info/Acme Corp.trashinfo";
+
+    fn parse(info: &str, text: &str) -> Result<TrashInfo, Error> {
+        TrashInfo::parse(Path::new(info), text)
+    }
+
+    #[test]
+    fn reads_path_and_deletion_date() {
+        let info = parse(
+            INFO,
+            "[Trash Info]\nPath=/srv/share/Acme%20Corp\nDeletionDate=2024-03-01T10:15:00\n",
+        )
+        .unwrap();
+        assert_eq!(info.original, Path::new("/srv/share/Acme Corp"));
+        assert_eq!(
+            info.deleted_at,
+            NaiveDateTime::parse_from_str("2024-03-01T10:15:00", DELETION_DATE_FORMAT).ok()
+        );
+        assert_eq!(
+            info.trashed,
+            Path::new("/home/ann/.local/share/Trash/files/Acme Corp")
+        );
+        assert_eq!(info.info, Path::new(INFO));
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(
+            decode_path("/plain/path").unwrap(),
+            Path::new("/plain/path")
+        );
+        assert_eq!(
+            decode_path("/srv/Acme%2C%20%22Inc%22/%C3%A9t%C3%A9").unwrap(),
+            Path::new("/srv/Acme, \"Inc\"/\u{e9}t\u{e9}")
+        );
+        assert_eq!(
+            decode_path("/srv/50%25%2fhalf").unwrap(),
+            Path::new("/srv/50%/half")
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_escapes() {
+        for value in ["/srv/100%", "/srv/%4", "/srv/%zz", "/srv/%+1"] {
+            assert_eq!(decode_path(value), None, "{}", value);
+        }
+        let text = "[Trash Info]\nPath=/srv/%zz\nDeletionDate=2024-03-01T10:15:00\n";
+        assert!(matches!(parse(INFO, text), Err(Error::Invalid(_))));
+    }
+
+    #[test]
+    fn relative_paths_start_at_the_volume_top() {
+        let text = "[Trash Info]\nPath=clients/Acme\nDeletionDate=2024-03-01T10:15:00\n";
+        let info = parse("/mnt/usb/.Trash-1000/info/Acme.trashinfo", text).unwrap();
+        assert_eq!(info.original, Path::new("/mnt/usb/clients/Acme"));
+        assert_eq!(info.trashed, Path::new("/mnt/usb/.Trash-1000/files/Acme"));
+
+        let info = parse("/mnt/usb/.Trash/1000/info/Acme.trashinfo", text).unwrap();
+        assert_eq!(info.original, Path::new("/mnt/usb/clients/Acme"));
+        assert_eq!(info.trashed, Path::new("/mnt/usb/.Trash/1000/files/Acme"));
+    }
+
+    #[test]
+    fn needs_the_trash_info_section() {
+        let text = "Path=/srv/Acme\nDeletionDate=2024-03-01T10:15:00\n";
+        assert!(matches!(parse(INFO, text), Err(Error::Invalid(_))));
+
+        let text = "[Other]\nPath=/srv/Acme\n";
+        assert!(matches!(parse(INFO, text), Err(Error::Invalid(_))));
+
+        // Keys of later sections are not the item's
+        let text = "[Trash Info]\nPath=/srv/Acme\n[Other]\nPath=/srv/Globex\n";
+        assert_eq!(parse(INFO, text).unwrap().original, Path::new("/srv/Acme"));
+    }
+
+    #[test]
+    fn tolerates_a_missing_or_unreadable_deletion_date() {
+        let info = parse(INFO, "[Trash Info]\nPath=/srv/Acme\n").unwrap();
+        assert_eq!(info.deleted_at, None);
+        assert!(!info.deleted_after(SystemTime::UNIX_EPOCH));
+
+        let text = "[Trash Info]\nPath=/srv/Acme\nDeletionDate=yesterday\n";
+        assert_eq!(parse(INFO, text).unwrap().deleted_at, None);
+    }
+
+    #[test]
+    fn needs_a_trashinfo_file_in_a_trash() {
+        let text = "[Trash Info]\nPath=/srv/Acme\n";
+        assert!(parse("/home/ann/Acme.txt", text).is_err());
+        assert!(parse("Acme.trashinfo", text).is_err());
+    }
+}
@@ -0,0 +1,294 @@
+use crate::{identity::DirIdentity, snapshot::DirSnapshot};
+use std::{
+    collections::{BTreeMap, HashMap},
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+};
+
+/// The tracked directories of a root, stored as a tree of path components
+/// so that a directory and everything below it are found, removed and
+/// listed together.
+///
+/// Paths are given as the monitor sees them, i.e. starting with the root's
+/// configured path.
+pub struct DirTree {
+    base: PathBuf,
+    top: Node,
+    /// Where each directory with a known identity is
+    by_identity: HashMap<DirIdentity, PathBuf>,
+    len: usize,
+}
+
+/// A directory in the tree. Nodes without a snapshot only exist to hold
+/// tracked directories further down.
+#[derive(Default)]
+struct Node {
+    snapshot: Option<DirSnapshot>,
+    children: BTreeMap<OsString, Node>,
+}
+
+impl DirTree {
+    pub fn new(base: PathBuf) -> DirTree {
+        DirTree {
+            base,
+            top: Node::default(),
+            by_identity: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    /// Path components of `path` below the root; `None` for paths outside
+    /// it and for the root itself.
+    fn components<'a>(&self, path: &'a Path) -> Option<Vec<&'a OsStr>> {
+        let relative = path.strip_prefix(&self.base).ok()?;
+        let names: Vec<_> = relative
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(name) => Some(name),
+                _ => None,
+            })
+            .collect();
+        (!names.is_empty()).then_some(names)
+    }
+
+    fn node(&self, path: &Path) -> Option<&Node> {
+        let mut node = &self.top;
+        for name in self.components(path)? {
+            node = node.children.get(name)?;
+        }
+        Some(node)
+    }
+
+    fn node_mut(&mut self, path: &Path) -> Option<&mut Node> {
+        let names = self.components(path)?;
+        let mut node = &mut self.top;
+        for name in names {
+            node = node.children.get_mut(name)?;
+        }
+        Some(node)
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&DirSnapshot> {
+        self.node(path)?.snapshot.as_ref()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.get(path).is_some()
+    }
+
+    /// Tracks `path`, replacing what was recorded for it before.
+    pub fn insert(&mut self, path: &Path, snapshot: DirSnapshot) {
+        let Some(names) = self.components(path) else {
+            return;
+        };
+        let mut node = &mut self.top;
+        for name in names {
+            node = node.children.entry(name.to_os_string()).or_default();
+        }
+        let identity = snapshot.identity;
+        let old = node.snapshot.replace(snapshot);
+        match old {
+            Some(old) => {
+                if let Some(old_identity) = old.identity.filter(|&i| Some(i) != identity) {
+                    self.forget_identity(&old_identity, path);
+                }
+            }
+            None => self.len += 1,
+        }
+        if let Some(identity) = identity {
+            self.by_identity.insert(identity, path.to_path_buf());
+        }
+    }
+
+    /// Drops `identity` from the index if it still points at `path`.
+    fn forget_identity(&mut self, identity: &DirIdentity, path: &Path) {
+        if self.by_identity.get(identity).is_some_and(|p| p == path) {
+            self.by_identity.remove(identity);
+        }
+    }
+
+    /// Replaces the snapshot of a tracked directory; untracked paths are
+    /// left alone.
+    pub fn update(&mut self, path: &Path, snapshot: DirSnapshot) {
+        if self.contains(path) {
+            self.insert(path, snapshot);
+        }
+    }
+
+    /// The snapshot of a tracked directory, for changing what is kept
+    /// alongside it; its identity must stay as it is.
+    pub fn get_mut(&mut self, path: &Path) -> Option<&mut DirSnapshot> {
+        self.node_mut(path)?.snapshot.as_mut()
+    }
+
+    /// Stops tracking `path` and every directory below it, returning what
+    /// was recorded for them, parents before children. Nothing is returned
+    /// if `path` itself was not tracked.
+    pub fn remove(&mut self, path: &Path) -> Vec<(PathBuf, DirSnapshot)> {
+        let Some(names) = self.components(path) else {
+            return Vec::new();
+        };
+        let Some((last, parents)) = names.split_last() else {
+            return Vec::new();
+        };
+        let mut node = &mut self.top;
+        for name in parents {
+            match node.children.get_mut(*name) {
+                Some(child) => node = child,
+                None => return Vec::new(),
+            }
+        }
+        let Some(removed) = node.children.remove(*last) else {
+            return Vec::new();
+        };
+
+        let mut removed_paths = Vec::new();
+        removed.collect(path.to_path_buf(), &mut removed_paths);
+        self.len -= removed_paths.len();
+        for (removed_path, snapshot) in &removed_paths {
+            if let Some(identity) = &snapshot.identity {
+                self.forget_identity(identity, removed_path);
+            }
+        }
+        if removed.snapshot.is_none() {
+            return Vec::new();
+        }
+        removed_paths
+            .into_iter()
+            .map(|(path, snapshot)| (path, snapshot.clone()))
+            .collect()
+    }
+
+    /// Where the directory with `identity` is tracked.
+    pub fn find_identity(&self, identity: &DirIdentity) -> Option<&Path> {
+        self.by_identity.get(identity).map(PathBuf::as_path)
+    }
+
+    /// Every tracked directory with its snapshot, parents before children.
+    pub fn entries(&self) -> Vec<(PathBuf, &DirSnapshot)> {
+        let mut entries = Vec::with_capacity(self.len);
+        self.top.collect(self.base.clone(), &mut entries);
+        entries
+    }
+
+    /// The tracked directories at and below `path`, parents before
+    /// children.
+    pub fn entries_under(&self, path: &Path) -> Vec<(PathBuf, &DirSnapshot)> {
+        if path == self.base {
+            return self.entries();
+        }
+        let mut entries = Vec::new();
+        if let Some(node) = self.node(path) {
+            node.collect(path.to_path_buf(), &mut entries);
+        }
+        entries
+    }
+}
+
+impl Node {
+    /// Appends this node, at `path`, and the tracked nodes below it.
+    fn collect<'a>(&'a self, path: PathBuf, into: &mut Vec<(PathBuf, &'a DirSnapshot)>) {
+        if let Some(snapshot) = &self.snapshot {
+            into.push((path.clone(), snapshot));
+        }
+        for (name, child) in &self.children {
+            child.collect(path.join(name), into);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn snapshot(ino: u64) -> DirSnapshot {
+        DirSnapshot {
+            identity: Some(DirIdentity {
+                dev: 1,
+                ino,
+                created: None,
+            }),
+            ..DirSnapshot::default()
+        }
+    }
+
+    /// A tree under `root` tracking `paths`, each with its position in the
+    /// list as inode number.
+    fn tree(paths: &[&str]) -> DirTree {
+        let mut tree = DirTree::new(PathBuf::from("root"));
+        for (ino, path) in paths.iter().enumerate() {
+            tree.insert(Path::new(path), snapshot(ino as u64));
+        }
+        tree
+    }
+
+    fn paths(entries: Vec<(PathBuf, impl Sized)>) -> Vec<PathBuf> {
+        entries.into_iter().map(|(path, _)| path).collect()
+    }
+
+    #[test]
+    fn remove_takes_the_subtree_parents_first() {
+        let mut tree = tree(&["root/a", "root/a/b/c", "root/a/b", "root/ab", "root/z"]);
+        let removed = tree.remove(Path::new("root/a"));
+        assert_eq!(
+            paths(removed),
+            ["root/a", "root/a/b", "root/a/b/c"].map(PathBuf::from)
+        );
+        assert_eq!(
+            paths(tree.entries()),
+            ["root/ab", "root/z"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn remove_forgets_the_identities_of_the_subtree() {
+        let mut tree = tree(&["root/a", "root/a/b", "root/z"]);
+        tree.remove(Path::new("root/a"));
+        assert_eq!(tree.find_identity(&snapshot(1).identity.unwrap()), None);
+        assert_eq!(
+            tree.find_identity(&snapshot(2).identity.unwrap()),
+            Some(Path::new("root/z"))
+        );
+    }
+
+    #[test]
+    fn remove_of_an_untracked_path_returns_nothing() {
+        let mut tree = tree(&["root/a"]);
+        assert!(tree.remove(Path::new("root/b")).is_empty());
+        assert!(tree.remove(Path::new("root")).is_empty());
+        assert!(tree.remove(Path::new("elsewhere/a")).is_empty());
+        assert!(tree.contains(Path::new("root/a")));
+    }
+
+    #[test]
+    fn entries_under_lists_a_subtree_parents_first() {
+        let tree = tree(&["root/a/b", "root/a", "root/a/b/c", "root/a/d", "root/ab"]);
+        assert_eq!(
+            paths(tree.entries_under(Path::new("root/a"))),
+            ["root/a", "root/a/b", "root/a/b/c", "root/a/d"].map(PathBuf::from)
+        );
+        assert_eq!(paths(tree.entries_under(Path::new("root"))).len(), 5);
+        assert!(tree.entries_under(Path::new("root/x")).is_empty());
+    }
+
+    #[test]
+    fn entries_under_an_untracked_path_lists_the_tracked_below_it() {
+        let tree = tree(&["root/a/b/c", "root/a/b/d"]);
+        assert_eq!(
+            paths(tree.entries_under(Path::new("root/a"))),
+            ["root/a/b/c", "root/a/b/d"].map(PathBuf::from)
+        );
+    }
+
+    #[test]
+    fn insert_over_a_tracked_path_moves_its_identity() {
+        let mut tree = tree(&["root/a"]);
+        tree.insert(Path::new("root/a"), snapshot(7));
+        assert_eq!(tree.find_identity(&snapshot(0).identity.unwrap()), None);
+        assert_eq!(
+            tree.find_identity(&snapshot(7).identity.unwrap()),
+            Some(Path::new("root/a"))
+        );
+        assert_eq!(tree.entries().len(), 1);
+    }
+}
@@ -0,0 +1,39 @@
+use crate::{
+    log::JsonRecord,
+    sink::{Entry, EventSink},
+};
+use std::{io, time::Duration};
+use ureq::Agent;
+
+/// Posts every entry as a JSON object to a URL.
+pub struct WebhookSink {
+    url: String,
+    agent: Agent,
+}
+
+impl WebhookSink {
+    /// A request that takes longer than `timeout` fails.
+    pub fn new(url: String, timeout: Duration) -> WebhookSink {
+        let agent = Agent::config_builder()
+            .timeout_global(Some(timeout))
+            .build()
+            .new_agent();
+        WebhookSink { url, agent }
+    }
+}
+
+impl EventSink for WebhookSink {
+    fn describe(&self) -> String {
+        format!("webhook {}", self.url)
+    }
+
+    fn write(&mut self, entry: &Entry) -> io::Result<()> {
+        let body = serde_json::to_string(&JsonRecord::new(entry))?;
+        self.agent
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .send(&body)
+            .map_err(|e| io::Error::other(e.to_string()))?;
+        Ok(())
+    }
+}